@@ -0,0 +1,252 @@
+//! Portable image bundles: a tarball, optionally gzipped, with the manifest
+//! and files of one image, for moving images between environments without
+//! a network path to an IMGAPI server.
+//!
+//! A bundle holds `manifest.json`, a `SHA1SUMS` file in the format of
+//! `sha1sum` and the image files as `files/<index>`, in that order.
+
+use flate2::read::GzDecoder;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use sha1::{Digest, Sha1};
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+use crate::error::{ImgapiError, Result};
+use crate::manifest::Manifest;
+
+const MANIFEST: &str = "manifest.json";
+const CHECKSUMS: &str = "SHA1SUMS";
+
+#[doc = "An image unpacked from a bundle"]
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    pub manifest: Manifest,
+    //One path per entry of manifest.files.
+    pub files: Vec<PathBuf>,
+}
+
+/// Write a bundle of `manifest` and its `files`, one per entry of
+/// manifest.files, to `path`. Paths ending in .gz or .tgz are gzipped.
+pub fn export(
+    manifest: &Manifest,
+    files: &[impl AsRef<Path>],
+    path: impl AsRef<Path>,
+) -> Result<()> {
+    let path = path.as_ref();
+    let gzip = path
+        .extension()
+        .is_some_and(|ext| ext == "gz" || ext == "tgz");
+    let out = File::create(path)?;
+    if gzip {
+        write(manifest, files, GzEncoder::new(out, Compression::default()))?.finish()?;
+    } else {
+        write(manifest, files, out)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Write an uncompressed bundle to `writer` and return it.
+pub fn write<W: Write>(manifest: &Manifest, files: &[impl AsRef<Path>], writer: W) -> Result<W> {
+    if files.len() != manifest.files.len() {
+        return Err(ImgapiError::MissingFile {
+            uuid: manifest.uuid,
+            index: files.len().min(manifest.files.len()),
+        });
+    }
+    let mut checksums = String::new();
+    for (index, file) in files.iter().enumerate() {
+        let actual = hex::encode(hash(File::open(file)?, &mut std::io::sink())?);
+        if let Some(expected) = manifest_sha1(manifest, index) {
+            check(&expected, &actual)?;
+        }
+        checksums.push_str(&format!("{}  files/{}\n", actual, index));
+    }
+
+    let mut tar = tar::Builder::new(writer);
+    let data = serde_json::to_vec_pretty(manifest)?;
+    append(&mut tar, MANIFEST, data.len() as u64, data.as_slice())?;
+    append(
+        &mut tar,
+        CHECKSUMS,
+        checksums.len() as u64,
+        checksums.as_bytes(),
+    )?;
+    for (index, file) in files.iter().enumerate() {
+        let file = File::open(file)?;
+        let size = file.metadata()?.len();
+        append(&mut tar, &format!("files/{}", index), size, file)?;
+    }
+    Ok(tar.into_inner()?)
+}
+
+/// Unpack the bundle at `path`, gzipped or not, into `dir` after checking
+/// every file against the bundled checksums and the manifest. Files are
+/// named `<uuid>.<index>.file`.
+pub fn import(path: impl AsRef<Path>, dir: impl AsRef<Path>) -> Result<Bundle> {
+    read(File::open(path)?, dir)
+}
+
+/// Like import, for a bundle read from `reader`.
+pub fn read<R: Read>(reader: R, dir: impl AsRef<Path>) -> Result<Bundle> {
+    let mut reader = BufReader::new(reader);
+    let gzip = reader.fill_buf()?.starts_with(&[0x1f, 0x8b]);
+    let reader: Box<dyn Read> = if gzip {
+        Box::new(GzDecoder::new(reader))
+    } else {
+        Box::new(reader)
+    };
+
+    //Files are unpacked to temporary names and only renamed into place once
+    //all of them verified, on any error every file written is removed
+    let mut written = vec![];
+    let bundle = unpack(reader, dir.as_ref(), &mut written).and_then(|mut bundle| {
+        for file in bundle.files.iter_mut() {
+            let done = file.with_extension("");
+            std::fs::rename(&*file, &done)?;
+            written.push(done.clone());
+            *file = done;
+        }
+        Ok(bundle)
+    });
+    if bundle.is_err() {
+        for path in &written {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    bundle
+}
+
+//Unpack into `.part` files, recording every path created in `written`.
+fn unpack(reader: impl Read, dir: &Path, written: &mut Vec<PathBuf>) -> Result<Bundle> {
+    let mut manifest: Option<Manifest> = None;
+    let mut checksums: Option<Vec<(String, String)>> = None;
+    let mut files: Vec<(usize, PathBuf, String)> = vec![];
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        match name.as_str() {
+            MANIFEST => {
+                let mut data = vec![];
+                entry.read_to_end(&mut data)?;
+                manifest = Some(serde_json::from_slice(&data)?);
+            }
+            CHECKSUMS => {
+                let mut data = String::new();
+                entry.read_to_string(&mut data)?;
+                checksums = Some(
+                    data.lines()
+                        .filter_map(|line| line.split_once("  "))
+                        .map(|(sha1, name)| (name.to_string(), sha1.to_ascii_lowercase()))
+                        .collect(),
+                );
+            }
+            _ => {
+                let Some(index) = name.strip_prefix("files/") else {
+                    log::debug!("ignoring {} in bundle", name);
+                    continue;
+                };
+                let index = index
+                    .parse::<usize>()
+                    .map_err(|_| invalid(format!("unexpected bundle entry {}", name)))?;
+                let uuid = manifest
+                    .as_ref()
+                    .ok_or_else(|| invalid(format!("{} precedes {}", name, MANIFEST)))?
+                    .uuid;
+                if files.iter().any(|(seen, _, _)| *seen == index) {
+                    return Err(invalid(format!("duplicate bundle entry {}", name)));
+                }
+                let path = dir.join(format!("{}.{}.file.part", uuid, index));
+                let mut file = File::create(&path)?;
+                written.push(path.clone());
+                let sha1 = hex::encode(hash(&mut entry, &mut file)?);
+                files.push((index, path, sha1));
+            }
+        }
+    }
+
+    let manifest = manifest.ok_or_else(|| invalid(format!("bundle has no {}", MANIFEST)))?;
+    let checksums = checksums.ok_or_else(|| invalid(format!("bundle has no {}", CHECKSUMS)))?;
+    let mut paths = vec![None; manifest.files.len()];
+    for (index, path, actual) in files {
+        let name = format!("files/{}", index);
+        let listed = checksums
+            .iter()
+            .find(|(file, _)| *file == name)
+            .map(|(_, sha1)| sha1)
+            .ok_or_else(|| invalid(format!("{} has no checksum", name)))?;
+        let verified = check(listed, &actual).and_then(|()| {
+            manifest_sha1(&manifest, index).map_or(Ok(()), |expected| check(&expected, &actual))
+        });
+        verified?;
+        *paths
+            .get_mut(index)
+            .ok_or_else(|| invalid(format!("{} is not in the manifest", name)))? = Some(path);
+    }
+    let files = paths
+        .into_iter()
+        .enumerate()
+        .map(|(index, path)| {
+            path.ok_or(ImgapiError::MissingFile {
+                uuid: manifest.uuid,
+                index,
+            })
+        })
+        .collect::<Result<_>>()?;
+    Ok(Bundle { manifest, files })
+}
+
+fn append<W: Write>(
+    tar: &mut tar::Builder<W>,
+    name: &str,
+    size: u64,
+    data: impl Read,
+) -> Result<()> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(size);
+    header.set_mode(0o644);
+    tar.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+fn manifest_sha1(manifest: &Manifest, index: usize) -> Option<String> {
+    manifest
+        .files
+        .get(index)
+        .map(|file| file.sha1.to_ascii_lowercase())
+}
+
+//Copy `reader` into `writer`, returning the SHA-1 of the data.
+fn hash(mut reader: impl Read, writer: &mut impl Write) -> Result<[u8; 20]> {
+    let mut hasher = Sha1::new();
+    let mut buf = vec![0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buf[..n]);
+        writer.write_all(&buf[..n])?;
+    }
+    writer.flush()?;
+    Ok(hasher.finalize().into())
+}
+
+fn check(expected: &str, actual: &str) -> Result<()> {
+    if expected != actual {
+        return Err(ImgapiError::ChecksumMismatch {
+            expected: expected.to_string(),
+            actual: actual.to_string(),
+        });
+    }
+    Ok(())
+}
+
+fn invalid(message: String) -> ImgapiError {
+    ImgapiError::Io(std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        message,
+    ))
+}
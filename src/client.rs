@@ -0,0 +1,1271 @@
+use futures::{stream, Stream, StreamExt, TryStreamExt};
+use reqwest::{
+    header::{ACCEPT, CONTENT_LENGTH, CONTENT_TYPE, RANGE},
+    Body, Method, RequestBuilder, Response, StatusCode,
+};
+use serde::{de::DeserializeOwned, Deserialize};
+use sha1::{Digest, Sha1};
+use std::io::SeekFrom;
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncSeekExt, AsyncWrite, AsyncWriteExt};
+use tokio_util::io::ReaderStream;
+use url::Url;
+use uuid::Uuid;
+
+use crate::error::{FieldError, ImgapiError, ImgapiErrorCode, Result};
+use crate::id::ImageId;
+use crate::manifest::Manifest;
+use crate::ser::SerializeConfig;
+
+mod auth;
+#[cfg(feature = "blocking")]
+pub mod blocking;
+mod builder;
+mod cache;
+mod digest;
+mod docker;
+mod download;
+mod icon;
+mod info;
+#[cfg(feature = "install")]
+mod install;
+mod json_stream;
+mod progress;
+mod query;
+mod request;
+mod retry;
+#[cfg(feature = "signing")]
+pub mod signing;
+mod throttle;
+
+pub use auth::{AuthProvider, BearerToken, HeaderAuth, NoAuth};
+pub use builder::ClientBuilder;
+pub use cache::MetadataCache;
+pub use digest::{DigestReader, Digests, MultiDigest};
+pub use docker::{
+    DockerImportEvent, DockerImportOptions, DockerImportOptionsBuilder, DockerProgress,
+    ProgressDetail,
+};
+pub use download::{DownloadProgress, DownloadedImage, Downloader};
+pub use icon::{IconType, MAX_ICON_SIZE};
+pub use info::ServerInfo;
+#[cfg(feature = "install")]
+pub use install::ZfsReceive;
+#[cfg(feature = "indicatif")]
+pub use progress::IndicatifProgress;
+pub use progress::{NoProgress, ProgressObserver, Transfer, TransferPhase};
+use query::{advance_page, first_page};
+pub use query::{
+    AclAction, AddImageFileParams, AddImageFileParamsBuilder, AdminImportOptions,
+    AdminImportOptionsBuilder, DeleteImageOptions, DeleteImageOptionsBuilder, ListImagesQuery,
+    ListImagesQueryBuilder, StateFilter, UpdateImagePayload, UpdateImagePayloadBuilder,
+};
+pub use retry::{RetryPolicy, RetryPolicyBuilder};
+pub use throttle::Throttle;
+
+#[doc = "Async client for an IMGAPI server such as https://images.smartos.org"]
+#[derive(Debug, Clone)]
+pub struct Client {
+    base_url: Url,
+    http: reqwest::Client,
+    //Sent with every image call, see with_channel.
+    channel: Option<String>,
+    auth: Arc<dyn AuthProvider>,
+    retry: RetryPolicy,
+    cache: Option<Arc<MetadataCache>>,
+    //Set through ClientBuilder::read_timeout.
+    read_timeout: Option<Duration>,
+    progress: Arc<dyn ProgressObserver>,
+    throttle: Option<Throttle>,
+    //Servers image files are downloaded from in order, see with_mirrors.
+    file_servers: Arc<Vec<FileServer>>,
+}
+
+impl Client {
+    pub fn new(base_url: &str) -> Result<Self> {
+        Ok(Self::with_http_client(
+            base_url.parse()?,
+            reqwest::Client::new(),
+        ))
+    }
+
+    /// Use a preconfigured reqwest client, e.g. with custom timeouts.
+    pub fn with_http_client(base_url: Url, http: reqwest::Client) -> Self {
+        let base_url = normalize_base_url(base_url);
+        Self {
+            file_servers: Arc::new(vec![FileServer {
+                base_url: base_url.clone(),
+                authenticated: true,
+            }]),
+            base_url,
+            http,
+            channel: None,
+            auth: Arc::new(NoAuth),
+            retry: RetryPolicy::none(),
+            cache: None,
+            read_timeout: None,
+            progress: Arc::new(NoProgress),
+            throttle: None,
+        }
+    }
+
+    /// Configure timeouts and connection pooling before creating a client.
+    pub fn builder(base_url: impl Into<String>) -> ClientBuilder {
+        ClientBuilder::new(base_url)
+    }
+
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// A client whose image calls target `channel` on a channel enabled
+    /// server. Cheap enough to use for a single request. A channel set on a
+    /// ListImagesQuery or DeleteImageOptions takes precedence.
+    pub fn with_channel(&self, channel: impl Into<String>) -> Self {
+        Self {
+            channel: Some(channel.into()),
+            ..self.clone()
+        }
+    }
+
+    pub fn channel(&self) -> Option<&str> {
+        self.channel.as_deref()
+    }
+
+    /// A client that signs every request with `signer`, for servers that
+    /// require HTTP Signature authentication.
+    #[cfg(feature = "signing")]
+    pub fn with_signer(&self, signer: impl signing::Signer + 'static) -> Self {
+        self.with_auth(signer)
+    }
+
+    /// A client that repeats requests failing with transient errors
+    /// according to `retry`.
+    pub fn with_retry(&self, retry: RetryPolicy) -> Self {
+        Self {
+            retry,
+            ..self.clone()
+        }
+    }
+
+    /// A client that sends ListImages and GetImage as conditional requests
+    /// and answers them from `cache` when the server reports no change.
+    /// Only share a cache between clients using the same credentials.
+    pub fn with_cache(&self, cache: Arc<MetadataCache>) -> Self {
+        Self {
+            cache: Some(cache),
+            ..self.clone()
+        }
+    }
+
+    /// A client that lets `auth` add credentials to every request.
+    pub fn with_auth(&self, auth: impl AuthProvider + 'static) -> Self {
+        Self {
+            auth: Arc::new(auth),
+            ..self.clone()
+        }
+    }
+
+    /// A client that reports image file downloads and uploads to `progress`.
+    pub fn with_progress(&self, progress: impl ProgressObserver + 'static) -> Self {
+        Self {
+            progress: Arc::new(progress),
+            ..self.clone()
+        }
+    }
+
+    /// A client that limits the bandwidth of image file downloads and
+    /// uploads. Clones of `throttle` share one limit, also across clients.
+    pub fn with_throttle(&self, throttle: Throttle) -> Self {
+        Self {
+            throttle: Some(throttle),
+            ..self.clone()
+        }
+    }
+
+    /// A client that downloads image files from `mirrors` when the server
+    /// and the mirrors added before fail to deliver them, trying them in
+    /// order. Mirrors only serve files, which are verified against the SHA-1
+    /// of this server's manifests. They are sent no credentials, see
+    /// with_authenticated_mirrors.
+    ///
+    /// Downloads into files, like get_image_file_resume and Downloader, move
+    /// on to the next server on any failure, including a broken transfer or
+    /// a wrong SHA-1. Streams into a writer only move on while no data was
+    /// sent, as written data cannot be taken back.
+    pub fn with_mirrors(&self, mirrors: impl IntoIterator<Item = Url>) -> Self {
+        self.add_mirrors(mirrors, false)
+    }
+
+    /// Like with_mirrors, for mirrors that get the same credentials as this
+    /// server. Only use it for mirrors run by the same operator.
+    pub fn with_authenticated_mirrors(&self, mirrors: impl IntoIterator<Item = Url>) -> Self {
+        self.add_mirrors(mirrors, true)
+    }
+
+    fn add_mirrors(&self, mirrors: impl IntoIterator<Item = Url>, authenticated: bool) -> Self {
+        let mut servers = self.file_servers.to_vec();
+        servers.extend(mirrors.into_iter().map(|url| FileServer {
+            base_url: normalize_base_url(url),
+            authenticated,
+        }));
+        Self {
+            file_servers: Arc::new(servers),
+            ..self.clone()
+        }
+    }
+
+    /// Order the server and its mirrors by the latency of a ping, fastest
+    /// first. Servers that do not answer go last.
+    pub async fn probe_mirrors(&self) -> Self {
+        let mut timed = vec![];
+        for server in self.file_servers.iter() {
+            let start = std::time::Instant::now();
+            let base = &server.base_url;
+            let req = self
+                .http
+                .get(base.join("ping").unwrap_or_else(|_| base.clone()));
+            let latency = match self.send_to(server, req).await {
+                Ok(_) => start.elapsed(),
+                Err(_) => Duration::MAX,
+            };
+            timed.push((latency, server.clone()));
+        }
+        //Stable, so equally fast servers keep their order
+        timed.sort_by_key(|(latency, _)| *latency);
+        Self {
+            file_servers: Arc::new(timed.into_iter().map(|(_, server)| server).collect()),
+            ..self.clone()
+        }
+    }
+
+    /// Ping: check that the server is up and find out which IMGAPI version
+    /// it runs.
+    pub async fn ping(&self) -> Result<ServerInfo> {
+        let req = self
+            .http
+            .get(self.url("ping")?)
+            .header(ACCEPT, "application/json");
+        json(self.send(req).await?).await
+    }
+
+    /// ListChannels: the channels of a channel enabled server.
+    pub async fn list_channels(&self) -> Result<Vec<Channel>> {
+        let req = self
+            .http
+            .get(self.url("channels")?)
+            .header(ACCEPT, "application/json");
+        json(self.send(req).await?).await
+    }
+
+    /// ListImages: all active images visible to the caller.
+    pub async fn list_images(&self) -> Result<Vec<Manifest>> {
+        self.list_images_with(&ListImagesQuery::default()).await
+    }
+
+    /// ListImages restricted by server side filters.
+    pub async fn list_images_with(&self, query: &ListImagesQuery) -> Result<Vec<Manifest>> {
+        let mut query = query.clone();
+        if query.channel.is_none() {
+            query.channel.clone_from(&self.channel);
+        }
+        let req = self.http.get(self.url("images")?).query(&query.to_query());
+        self.get_json(req).await
+    }
+
+    /// ListImages parsing the response while it arrives, yielding each
+    /// manifest as soon as it is complete. Keeps memory flat for the
+    /// thousands of images of a public server. Bypasses the cache.
+    pub async fn list_images_streamed(
+        &self,
+        query: &ListImagesQuery,
+    ) -> Result<impl Stream<Item = Result<Manifest>>> {
+        let mut query = query.clone();
+        if query.channel.is_none() {
+            query.channel.clone_from(&self.channel);
+        }
+        let req = self.http.get(self.url("images")?).query(&query.to_query());
+        Ok(json_stream::json_array(self.send(req).await?))
+    }
+
+    /// ListImages following limit/marker paging until all matching images
+    /// are returned. The query's limit is used as the page size.
+    pub fn list_images_paged(
+        &self,
+        query: ListImagesQuery,
+    ) -> impl Stream<Item = Result<Manifest>> + '_ {
+        stream::try_unfold(Some(first_page(query)), move |query| async move {
+            let Some(query) = query else {
+                return Ok::<_, ImgapiError>(None);
+            };
+            let mut page = self.list_images_with(&query).await?;
+            let next = advance_page(query, &mut page);
+            Ok(Some((page, next)))
+        })
+        .map_ok(|page| stream::iter(page.into_iter().map(Ok)))
+        .try_flatten()
+    }
+
+    /// GetImage: a single image by UUID.
+    pub async fn get_image(&self, uuid: Uuid) -> Result<Manifest> {
+        let req = self.request(Method::GET, &format!("images/{}", uuid))?;
+        self.get_json(req).await
+    }
+
+    /// The image `id` refers to: GetImage for a UUID, otherwise a lookup in
+    /// ListImages, see [`ImageId::select`].
+    pub async fn resolve(&self, id: &ImageId) -> Result<Manifest> {
+        let query = match id {
+            ImageId::Uuid(uuid) => return self.get_image(*uuid).await,
+            ImageId::Prefix(_) => ListImagesQuery::default(),
+            ImageId::NameVersion { name, version } => ListImagesQuery {
+                name: Some(name.clone()),
+                version: Some(version.clone()),
+                ..Default::default()
+            },
+        };
+        let images: Vec<Manifest> = self.list_images_paged(query).try_collect().await?;
+        id.select(images)
+    }
+
+    /// GetImage with inclAdminFields, see [`Manifest::admin_fields`].
+    /// Operator only.
+    pub async fn get_image_admin(&self, uuid: Uuid) -> Result<Manifest> {
+        let req = self
+            .request(Method::GET, &format!("images/{}", uuid))?
+            .query(&[("inclAdminFields", "true")]);
+        self.get_json(req).await
+    }
+
+    /// CreateImage: create a new, unactivated image from `manifest`. Fields
+    /// the server assigns (uuid, state, files, ...) are not sent; the
+    /// returned manifest carries their server-side values.
+    pub async fn create_image(&self, manifest: &Manifest) -> Result<Manifest> {
+        let req = self
+            .request(Method::POST, "images")?
+            .json(&create_image_body(manifest)?);
+        json(self.send(req).await?).await
+    }
+
+    /// UpdateImage: change the fields set in `payload`, returns the updated
+    /// manifest.
+    pub async fn update_image(&self, uuid: Uuid, payload: &UpdateImagePayload) -> Result<Manifest> {
+        let req = self
+            .request(Method::POST, &format!("images/{}", uuid))?
+            .query(&[("action", "update")])
+            .json(&SerializeConfig::default().to_value(payload)?);
+        json(self.send(req).await?).await
+    }
+
+    /// AdminImportImage: create an image as is, keeping the uuid, owner and
+    /// other server assigned fields of `manifest`. Operator only.
+    pub async fn admin_import_image(
+        &self,
+        manifest: &Manifest,
+        options: &AdminImportOptions,
+    ) -> Result<Manifest> {
+        let req = self
+            .request(Method::POST, &format!("images/{}", manifest.uuid))?
+            .query(&[("action", "import")])
+            .query(&options.to_query())
+            .json(&SerializeConfig::default().to_value(manifest)?);
+        json(self.send(req).await?).await
+    }
+
+    /// AdminImportRemoteImage: have the server pull the image and its file
+    /// from the IMGAPI at `source`. The import runs as a workflow job on the
+    /// server. Operator only.
+    pub async fn admin_import_remote_image(
+        &self,
+        uuid: Uuid,
+        source: &Url,
+        skip_owner_check: bool,
+    ) -> Result<ImportJob> {
+        let options = AdminImportOptions {
+            skip_owner_check,
+            source: Some(source.clone()),
+        };
+        let req = self
+            .request(Method::POST, &format!("images/{}", uuid))?
+            .query(&[("action", "import-remote")])
+            .query(&options.to_query());
+        json(self.send(req).await?).await
+    }
+
+    /// AdminImportDockerImage: have the server pull an image from a Docker
+    /// registry. The returned stream yields the progress messages as the
+    /// server sends them and an error if the pull fails. Operator only.
+    pub async fn admin_import_docker_image(
+        &self,
+        options: &DockerImportOptions,
+    ) -> Result<impl Stream<Item = Result<DockerImportEvent>>> {
+        let mut req = self
+            .request(Method::POST, "images")?
+            .query(&options.to_query());
+        if let Some(auth) = &options.registry_auth {
+            req = req.header("x-registry-auth", auth);
+        }
+        Ok(docker::import_events(self.send(req).await?))
+    }
+
+    /// ActivateImage: make an unactivated image with its file uploaded
+    /// available for provisioning.
+    pub async fn activate_image(&self, uuid: Uuid) -> Result<Manifest> {
+        self.image_action(uuid, "activate", &[]).await
+    }
+
+    /// DisableImage: hide an active image from provisioning.
+    pub async fn disable_image(&self, uuid: Uuid) -> Result<Manifest> {
+        self.image_action(uuid, "disable", &[]).await
+    }
+
+    /// EnableImage: undo DisableImage.
+    pub async fn enable_image(&self, uuid: Uuid) -> Result<Manifest> {
+        self.image_action(uuid, "enable", &[]).await
+    }
+
+    /// AddImageAcl/RemoveImageAcl: grant or revoke access to a private
+    /// image, returns the updated manifest.
+    pub async fn update_acl(&self, uuid: Uuid, action: AclAction) -> Result<Manifest> {
+        let req = self
+            .request(Method::POST, &format!("images/{}/acl", uuid))?
+            .query(&[("action", action.action())])
+            .json(action.accounts());
+        json(self.send(req).await?).await
+    }
+
+    /// ChannelAddImage: add the image to `channel`, in addition to the
+    /// channel the client targets. Returns the manifest with the updated
+    /// `channels`.
+    pub async fn channel_add_image(&self, uuid: Uuid, channel: &str) -> Result<Manifest> {
+        let req = self
+            .request(Method::POST, &format!("images/{}", uuid))?
+            .query(&[("action", "channel-add")])
+            .json(&serde_json::json!({ "channel": channel }));
+        json(self.send(req).await?).await
+    }
+
+    /// AdminChangeImageStor: move the image files to the `stor` storage
+    /// backend, e.g. "manta" or "local". The returned manifest has the new
+    /// per-file `stor`. Operator only.
+    pub async fn admin_change_image_stor(&self, uuid: Uuid, stor: &str) -> Result<Manifest> {
+        self.image_action(uuid, "change-stor", &[("stor", stor.to_string())])
+            .await
+    }
+
+    /// CloneImage: copy a private image shared with `account` through its
+    /// ACL into that account. Returns the manifest of the new image, which
+    /// `account` owns.
+    pub async fn clone_image(&self, uuid: Uuid, account: Uuid) -> Result<Manifest> {
+        let req = self
+            .request(Method::POST, &format!("images/{}/clone", uuid))?
+            .query(&[("account", account)]);
+        json(self.send(req).await?).await
+    }
+
+    /// ExportImage: write the image file and manifest to `manta_path` in
+    /// Manta. A path ending in a slash is treated as a directory.
+    pub async fn export_image(&self, uuid: Uuid, manta_path: &str) -> Result<ExportLocation> {
+        self.image_action(uuid, "export", &[("manta_path", manta_path.to_string())])
+            .await
+    }
+
+    //POST /images/:uuid?action=...; the endpoint behind most image mutations.
+    async fn image_action<T: DeserializeOwned>(
+        &self,
+        uuid: Uuid,
+        action: &str,
+        query: &[(&str, String)],
+    ) -> Result<T> {
+        let req = self
+            .request(Method::POST, &format!("images/{}", uuid))?
+            .query(&[("action", action)])
+            .query(query);
+        json(self.send(req).await?).await
+    }
+
+    /// DeleteImage: remove the image and its file. Fails with
+    /// [`ImgapiError::HasDependentImages`] while incremental images built on
+    /// top of it exist.
+    pub async fn delete_image(&self, uuid: Uuid) -> Result<()> {
+        self.delete_image_with(uuid, &DeleteImageOptions::default())
+            .await
+    }
+
+    /// DeleteImage with channel options.
+    pub async fn delete_image_with(&self, uuid: Uuid, options: &DeleteImageOptions) -> Result<()> {
+        let mut options = options.clone();
+        if options.channel.is_none() {
+            options.channel.clone_from(&self.channel);
+        }
+        let req = self
+            .http
+            .delete(self.url(&format!("images/{}", uuid))?)
+            .header(ACCEPT, "application/json")
+            .query(&options.to_query());
+        self.send(req).await?;
+        Ok(())
+    }
+
+    /// GetImageFile: stream file `index` of the image into `writer` while
+    /// verifying it against the SHA-1 recorded in the manifest. Returns the
+    /// number of bytes written.
+    pub async fn get_image_file<W: AsyncWrite + Unpin>(
+        &self,
+        uuid: Uuid,
+        index: usize,
+        writer: &mut W,
+    ) -> Result<u64> {
+        let manifest = self.get_image(uuid).await?;
+        self.fetch_file(&manifest, index, writer, &mut |_| {}).await
+    }
+
+    //GetImageFile for a known manifest, calling `progress` with the number
+    //of bytes written so far after every chunk.
+    pub(crate) async fn fetch_file<W: AsyncWrite + Unpin>(
+        &self,
+        manifest: &Manifest,
+        index: usize,
+        writer: &mut W,
+        progress: &mut (dyn FnMut(u64) + Send),
+    ) -> Result<u64> {
+        let expected = expected_sha1(manifest, index)?;
+        let resp = self.send_file_request(manifest, index).await?;
+        let (hasher, written) = self
+            .copy_body(resp, Sha1::new(), 0, writer, manifest, index, progress)
+            .await?;
+        self.observe(
+            manifest.uuid,
+            index,
+            TransferPhase::Verifying,
+            written,
+            file_size(manifest, index),
+        );
+        verify_sha1(&expected, hasher)?;
+        self.observe(
+            manifest.uuid,
+            index,
+            TransferPhase::Finished,
+            written,
+            file_size(manifest, index),
+        );
+        Ok(written)
+    }
+
+    /// GetImageFile into the file at `path`, continuing a previous partial
+    /// download with a Range request. The existing content is hashed first,
+    /// so the SHA-1 still covers the whole file. Servers without range
+    /// support send the full file, which then replaces the partial one.
+    pub async fn get_image_file_resume(
+        &self,
+        uuid: Uuid,
+        index: usize,
+        path: impl AsRef<Path>,
+    ) -> Result<u64> {
+        let manifest = self.get_image(uuid).await?;
+        self.fetch_file_resume(&manifest, index, path.as_ref(), &mut |_| {})
+            .await
+    }
+
+    //Try the server and the mirrors in order until one delivers the whole
+    //file with the right SHA-1.
+    pub(crate) async fn fetch_file_resume(
+        &self,
+        manifest: &Manifest,
+        index: usize,
+        path: &Path,
+        progress: &mut (dyn FnMut(u64) + Send),
+    ) -> Result<u64> {
+        let Some((last, fallbacks)) = self.file_servers.split_last() else {
+            unreachable!("the server itself is always a file server");
+        };
+        for server in fallbacks {
+            match self
+                .fetch_file_resume_from(server, manifest, index, path, progress)
+                .await
+            {
+                Ok(written) => return Ok(written),
+                Err(e) => {
+                    log::warn!(
+                        "downloading {} from {} failed: {}",
+                        manifest.uuid,
+                        server.base_url,
+                        e
+                    );
+                    //A broken transfer resumes from the next server, bad content does not
+                    if matches!(e, ImgapiError::ChecksumMismatch { .. }) {
+                        tokio::fs::File::create(path).await?;
+                    }
+                }
+            }
+        }
+        self.fetch_file_resume_from(last, manifest, index, path, progress)
+            .await
+    }
+
+    async fn fetch_file_resume_from(
+        &self,
+        server: &FileServer,
+        manifest: &Manifest,
+        index: usize,
+        path: &Path,
+        progress: &mut (dyn FnMut(u64) + Send),
+    ) -> Result<u64> {
+        let expected = expected_sha1(manifest, index)?;
+        let size = file_size(manifest, index);
+        let mut file = tokio::fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(path)
+            .await?;
+
+        let mut hasher = Sha1::new();
+        let mut offset = 0u64;
+        let mut buf = vec![0u8; 64 * 1024];
+        loop {
+            let n = file.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            hasher.update(&buf[..n]);
+            offset += n as u64;
+        }
+
+        //Nothing left to fetch, unless the partial file is not what it claims
+        if offset > 0 && size.is_some_and(|size| offset >= size) {
+            if offset == size.unwrap_or_default()
+                && hex::encode(hasher.clone().finalize()) == expected
+            {
+                progress(offset);
+                self.observe(manifest.uuid, index, TransferPhase::Finished, offset, size);
+                return Ok(offset);
+            }
+            offset = 0;
+        }
+
+        let range = (offset > 0).then_some(offset);
+        let resp = self
+            .send_file_request_to(server, manifest, index, range)
+            .await?;
+        if offset == 0 || resp.status() != StatusCode::PARTIAL_CONTENT {
+            log::debug!("restarting download of {} from the start", manifest.uuid);
+            file.set_len(0).await?;
+            file.seek(SeekFrom::Start(0)).await?;
+            hasher = Sha1::new();
+            offset = 0;
+        }
+        let (hasher, written) = self
+            .copy_body(resp, hasher, offset, &mut file, manifest, index, progress)
+            .await?;
+        self.observe(
+            manifest.uuid,
+            index,
+            TransferPhase::Verifying,
+            written,
+            size,
+        );
+        verify_sha1(&expected, hasher)?;
+        self.observe(manifest.uuid, index, TransferPhase::Finished, written, size);
+        Ok(written)
+    }
+
+    /// Stream file `index` of `manifest` into `sink`, e.g. a [`ZfsReceive`],
+    /// decompressing it on the way according to the manifest. The SHA-1 is
+    /// verified on the compressed stream; on a mismatch the sink has
+    /// received bad data and whatever it created should be discarded.
+    /// Returns the sink once all data is written to it. The manifest of an
+    /// [`ImageId`] comes from [`Client::resolve`].
+    #[cfg(feature = "install")]
+    pub async fn install_image_file<W: std::io::Write + Send + 'static>(
+        &self,
+        manifest: &Manifest,
+        index: usize,
+        sink: W,
+    ) -> Result<W> {
+        let expected = expected_sha1(manifest, index)?;
+        let compression = manifest.files[index].compression.clone();
+        let total = file_size(manifest, index);
+        let resp = self.send_file_request(manifest, index).await?;
+
+        //Decompression and the sink may block, so they get their own thread
+        let (tx, mut rx) = tokio::sync::mpsc::channel::<bytes::Bytes>(16);
+        let writer = tokio::task::spawn_blocking(move || {
+            let mut decoder = install::Decoder::new(&compression, sink);
+            while let Some(chunk) = rx.blocking_recv() {
+                decoder.write_all(&chunk)?;
+            }
+            decoder.finish()
+        });
+
+        let mut body = resp.bytes_stream();
+        let mut hasher = Sha1::new();
+        let mut written = 0u64;
+        self.observe(manifest.uuid, index, TransferPhase::Downloading, 0, total);
+        let streamed = async {
+            while let Some(chunk) = self.next_chunk(&mut body).await? {
+                hasher.update(&chunk);
+                written += chunk.len() as u64;
+                if let Some(throttle) = &self.throttle {
+                    throttle.acquire(chunk.len() as u64).await;
+                }
+                //A closed channel means the writer failed, its error is reported below
+                if tx.send(chunk).await.is_err() {
+                    break;
+                }
+                self.observe(
+                    manifest.uuid,
+                    index,
+                    TransferPhase::Downloading,
+                    written,
+                    total,
+                );
+            }
+            Ok::<_, ImgapiError>(())
+        }
+        .await;
+        drop(tx);
+        let sink = writer
+            .await
+            .map_err(|_| std::io::Error::other("install writer panicked"))?;
+        streamed?;
+        //A failed writer stops the download early, so its error goes first
+        let sink = sink?;
+        self.observe(
+            manifest.uuid,
+            index,
+            TransferPhase::Verifying,
+            written,
+            total,
+        );
+        verify_sha1(&expected, hasher)?;
+        self.observe(
+            manifest.uuid,
+            index,
+            TransferPhase::Finished,
+            written,
+            total,
+        );
+        Ok(sink)
+    }
+
+    fn observe(
+        &self,
+        image: Uuid,
+        index: usize,
+        phase: TransferPhase,
+        transferred: u64,
+        total: Option<u64>,
+    ) {
+        self.progress.update(&Transfer {
+            image,
+            index,
+            phase,
+            transferred,
+            total,
+        });
+    }
+
+    //GetImageFile from the first server that answers, trying the mirrors in
+    //order.
+    async fn send_file_request(&self, manifest: &Manifest, index: usize) -> Result<Response> {
+        let Some((last, fallbacks)) = self.file_servers.split_last() else {
+            unreachable!("the server itself is always a file server");
+        };
+        for server in fallbacks {
+            match self
+                .send_file_request_to(server, manifest, index, None)
+                .await
+            {
+                Ok(resp) => return Ok(resp),
+                Err(e) => log::warn!(
+                    "downloading {} from {} failed: {}",
+                    manifest.uuid,
+                    server.base_url,
+                    e
+                ),
+            }
+        }
+        self.send_file_request_to(last, manifest, index, None).await
+    }
+
+    //GetImageFile from `server`, optionally only from byte `range` on.
+    async fn send_file_request_to(
+        &self,
+        server: &FileServer,
+        manifest: &Manifest,
+        index: usize,
+        range: Option<u64>,
+    ) -> Result<Response> {
+        let url = request::image_url(
+            &server.base_url,
+            &format!("images/{}/file", manifest.uuid),
+            self.channel.as_deref(),
+        )?;
+        let mut req = self.http.get(url).query(&[("index", index)]);
+        if let Some(offset) = range {
+            req = req.header(RANGE, format!("bytes={}-", offset));
+        }
+        self.send_to(server, req).await
+    }
+
+    //Write the response body to `writer`, continuing `hasher` and the count
+    //of bytes already `written`.
+    #[allow(clippy::too_many_arguments)]
+    async fn copy_body<W: AsyncWrite + Unpin>(
+        &self,
+        resp: Response,
+        mut hasher: Sha1,
+        mut written: u64,
+        writer: &mut W,
+        manifest: &Manifest,
+        index: usize,
+        progress: &mut (dyn FnMut(u64) + Send),
+    ) -> Result<(Sha1, u64)> {
+        let total = file_size(manifest, index);
+        self.observe(
+            manifest.uuid,
+            index,
+            TransferPhase::Downloading,
+            written,
+            total,
+        );
+        let mut body = resp.bytes_stream();
+        while let Some(chunk) = self.next_chunk(&mut body).await? {
+            hasher.update(&chunk);
+            writer.write_all(&chunk).await?;
+            written += chunk.len() as u64;
+            if let Some(throttle) = &self.throttle {
+                throttle.acquire(chunk.len() as u64).await;
+            }
+            progress(written);
+            self.observe(
+                manifest.uuid,
+                index,
+                TransferPhase::Downloading,
+                written,
+                total,
+            );
+        }
+        writer.flush().await?;
+        Ok((hasher, written))
+    }
+
+    /// AddImageFile: upload the image file from `reader`, streaming it as the
+    /// request body. Returns the updated manifest.
+    ///
+    /// The start of the file is checked against `params.compression` first,
+    /// files compressed differently or twice are rejected before sending.
+    pub async fn add_image_file<R>(
+        &self,
+        uuid: Uuid,
+        params: &AddImageFileParams,
+        reader: R,
+    ) -> Result<Manifest>
+    where
+        R: AsyncRead + Send + Sync + 'static,
+    {
+        let mut reader = Box::pin(reader);
+        let mut head = Vec::with_capacity(crate::compression::SNIFF_LEN);
+        (&mut reader)
+            .take(crate::compression::SNIFF_LEN as u64)
+            .read_to_end(&mut head)
+            .await?;
+        crate::compression::check(&params.compression, head.as_slice())?;
+        let reader = std::io::Cursor::new(head).chain(reader);
+
+        let total = params.size.map(|size| size.get());
+        let observer = self.progress.clone();
+        let mut sent = 0u64;
+        let throttle = self.throttle.clone();
+        let body = ReaderStream::new(reader)
+            .then(move |chunk| {
+                let throttle = throttle.clone();
+                async move {
+                    if let (Some(throttle), Ok(chunk)) = (&throttle, &chunk) {
+                        throttle.acquire(chunk.len() as u64).await;
+                    }
+                    chunk
+                }
+            })
+            .inspect(move |chunk| {
+                if let Ok(chunk) = chunk {
+                    sent += chunk.len() as u64;
+                    //The server verifies the SHA-1 once it has the whole file
+                    let phase = match total {
+                        Some(total) if sent >= total => TransferPhase::Verifying,
+                        _ => TransferPhase::Uploading,
+                    };
+                    observer.update(&Transfer {
+                        image: uuid,
+                        index: 0,
+                        phase,
+                        transferred: sent,
+                        total,
+                    });
+                }
+            });
+        let mut req = self
+            .request(Method::PUT, &format!("images/{}/file", uuid))?
+            .query(&params.to_query())
+            .header(CONTENT_TYPE, "application/octet-stream")
+            .body(Body::wrap_stream(body));
+        if let Some(size) = total {
+            req = req.header(CONTENT_LENGTH, size);
+        }
+        if let Some(md5) = &params.content_md5 {
+            req = req.header("content-md5", md5);
+        }
+        let manifest: Manifest = json(self.send(req).await?).await?;
+        let size = file_size(&manifest, 0).or(total);
+        self.observe(
+            uuid,
+            0,
+            TransferPhase::Finished,
+            size.unwrap_or_default(),
+            size,
+        );
+        Ok(manifest)
+    }
+
+    /// AddImageFile from a local file. The size is taken from the file if
+    /// `params` does not set it.
+    pub async fn add_image_file_from_path(
+        &self,
+        uuid: Uuid,
+        params: &AddImageFileParams,
+        path: impl AsRef<Path>,
+    ) -> Result<Manifest> {
+        let file = tokio::fs::File::open(path).await?;
+        let mut params = params.clone();
+        if params.size.is_none() {
+            params.size = Some(crate::units::Bytes(file.metadata().await?.len()));
+        }
+        self.add_image_file(uuid, &params, file).await
+    }
+
+    /// AddImageIcon: upload `data` as the image icon. The format is taken
+    /// from the magic bytes and the size is checked before uploading.
+    pub async fn add_image_icon(&self, uuid: Uuid, data: Vec<u8>) -> Result<Manifest> {
+        let icon_type = check_icon(&data)?;
+        let req = self
+            .request(Method::POST, &format!("images/{}/icon", uuid))?
+            .header(CONTENT_TYPE, icon_type.mime_type())
+            .body(data);
+        json(self.send(req).await?).await
+    }
+
+    /// GetImageIcon: stream the icon into `writer` and return its format.
+    pub async fn get_image_icon<W: AsyncWrite + Unpin>(
+        &self,
+        uuid: Uuid,
+        writer: &mut W,
+    ) -> Result<IconType> {
+        let url = request::image_url(
+            &self.base_url,
+            &format!("images/{}/icon", uuid),
+            self.channel.as_deref(),
+        )?;
+        let req = self.http.get(url).header(ACCEPT, icon::ACCEPT_ICON);
+        let resp = self.send(req).await?;
+        let icon_type = icon_type_of(&resp)?;
+
+        let mut body = resp.bytes_stream();
+        while let Some(chunk) = self.next_chunk(&mut body).await? {
+            writer.write_all(&chunk).await?;
+        }
+        writer.flush().await?;
+        Ok(icon_type)
+    }
+
+    /// DeleteImageIcon: remove the icon, returns the updated manifest.
+    pub async fn delete_image_icon(&self, uuid: Uuid) -> Result<Manifest> {
+        let req = self.request(Method::DELETE, &format!("images/{}/icon", uuid))?;
+        json(self.send(req).await?).await
+    }
+
+    fn url(&self, path: &str) -> Result<Url> {
+        Ok(self.base_url.join(path)?)
+    }
+
+    //A request to a JSON image endpoint.
+    fn request(&self, method: Method, path: &str) -> Result<RequestBuilder> {
+        let url = request::image_url(&self.base_url, path, self.channel.as_deref())?;
+        Ok(self.http.request(method, url).header(ACCEPT, request::JSON))
+    }
+
+    //The next chunk of a streamed body, subject to the read timeout.
+    async fn next_chunk<S, T>(&self, body: &mut S) -> Result<Option<T>>
+    where
+        S: Stream<Item = reqwest::Result<T>> + Unpin,
+    {
+        let next = match self.read_timeout {
+            Some(limit) => tokio::time::timeout(limit, body.next())
+                .await
+                .map_err(|_| {
+                    std::io::Error::new(
+                        std::io::ErrorKind::TimedOut,
+                        "no data received within the read timeout",
+                    )
+                })?,
+            None => body.next().await,
+        };
+        Ok(next.transpose()?)
+    }
+
+    //GET a JSON document through the metadata cache, if there is one.
+    async fn get_json<T: DeserializeOwned>(&self, req: RequestBuilder) -> Result<T> {
+        let Some(cache) = &self.cache else {
+            return json(self.send(req).await?).await;
+        };
+        let mut req = req.build()?;
+        let url = req.url().clone();
+        let key = request::add_cache_conditions(cache, &url, req.headers_mut());
+        let resp = self.send_request(req).await?;
+        let headers = resp.headers().clone();
+        let fetched = match resp.status() {
+            StatusCode::NOT_MODIFIED => None,
+            _ => Some(resp.bytes().await?.to_vec()),
+        };
+        let body = request::cached_body(cache, &key, &headers, fetched)?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    async fn send(&self, req: RequestBuilder) -> Result<Response> {
+        self.send_request(req.build()?).await
+    }
+
+    //Send to a file server, with credentials only if it gets them.
+    async fn send_to(&self, server: &FileServer, req: RequestBuilder) -> Result<Response> {
+        let auth = server.authenticated.then_some(&*self.auth);
+        self.send_with(req.build()?, auth).await
+    }
+
+    async fn send_request(&self, req: reqwest::Request) -> Result<Response> {
+        self.send_with(req, Some(&*self.auth)).await
+    }
+
+    async fn send_with(
+        &self,
+        mut req: reqwest::Request,
+        auth: Option<&dyn AuthProvider>,
+    ) -> Result<Response> {
+        let mut attempt = 1;
+        let resp = loop {
+            let next = request::prepare_attempt(&self.retry, auth, &mut req, attempt)?;
+            let result = self.http.execute(req).await;
+            let outcome = result.as_ref().map(|resp| (resp.status(), resp.headers()));
+            match (next, request::retry_delay(&self.retry, outcome, attempt)) {
+                (Some(next), Some(delay)) => {
+                    tokio::time::sleep(delay).await;
+                    req = next;
+                    attempt += 1;
+                }
+                _ => break result?,
+            }
+        };
+        match request::failure(resp.status(), resp.headers()) {
+            None => Ok(resp),
+            Some((status, retry_after)) => {
+                let body = resp.text().await.unwrap_or_default();
+                Err(api_error(status, retry_after, &body))
+            }
+        }
+    }
+}
+
+#[doc = "Error type for the builders of client parameters and settings"]
+#[derive(Debug, thiserror::Error, miette::Diagnostic)]
+#[non_exhaustive]
+pub enum ParamsBuilderError {
+    #[error("field {0} must be initialized")]
+    UninitializedField(&'static str),
+    #[error("validation error: {0}")]
+    ValidationError(String),
+}
+
+impl From<String> for ParamsBuilderError {
+    fn from(s: String) -> Self {
+        Self::ValidationError(s)
+    }
+}
+
+impl From<derive_builder::UninitializedFieldError> for ParamsBuilderError {
+    fn from(value: derive_builder::UninitializedFieldError) -> Self {
+        Self::UninitializedField(value.field_name())
+    }
+}
+
+//The server or a mirror, see Client::with_mirrors.
+#[derive(Debug, Clone)]
+struct FileServer {
+    base_url: Url,
+    //Whether requests carry the credentials of the client.
+    authenticated: bool,
+}
+
+#[doc = "A channel of a channel enabled IMGAPI server, from ListChannels"]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct Channel {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    //Set on the channel used when a request names none.
+    #[serde(default)]
+    pub default: bool,
+}
+
+#[doc = "The workflow job started by AdminImportRemoteImage"]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct ImportJob {
+    pub image_uuid: Uuid,
+    pub job_uuid: Uuid,
+}
+
+#[doc = "Where ExportImage stored an image in Manta"]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct ExportLocation {
+    pub manta_url: Url,
+    //Manta paths of the exported image file and manifest.
+    pub image_path: String,
+    pub manifest_path: String,
+}
+
+async fn json<T: DeserializeOwned>(resp: Response) -> Result<T> {
+    let body = resp.bytes().await?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+//Url::join replaces the last path segment unless the base ends with a slash.
+pub(crate) fn normalize_base_url(mut url: Url) -> Url {
+    if !url.path().ends_with('/') {
+        let path = format!("{}/", url.path());
+        url.set_path(&path);
+    }
+    url
+}
+
+//Manifest fields CreateImage rejects because the server sets them.
+const SERVER_ASSIGNED_FIELDS: &[&str] = &[
+    "v",
+    "uuid",
+    "state",
+    "error",
+    "files",
+    "published_at",
+    "icon",
+];
+
+fn create_image_body(manifest: &Manifest) -> Result<serde_json::Value> {
+    let mut body = SerializeConfig::default().to_value(manifest)?;
+    if let Some(obj) = body.as_object_mut() {
+        for field in SERVER_ASSIGNED_FIELDS {
+            obj.remove(*field);
+        }
+        if manifest.owner.is_nil() {
+            obj.remove("owner");
+        }
+    }
+    Ok(body)
+}
+
+pub(crate) fn check_icon(data: &[u8]) -> Result<IconType> {
+    if data.len() > MAX_ICON_SIZE {
+        return Err(ImgapiError::IconTooLarge {
+            size: data.len(),
+            max: MAX_ICON_SIZE,
+        });
+    }
+    IconType::sniff(data).ok_or(ImgapiError::UnsupportedIcon)
+}
+
+fn icon_type_of(resp: &Response) -> Result<IconType> {
+    resp.headers()
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .and_then(|mime| mime.trim().parse().ok())
+        .ok_or(ImgapiError::UnsupportedIcon)
+}
+
+//The cache entry was cleared between sending the request and the 304.
+pub(crate) fn not_cached() -> ImgapiError {
+    ImgapiError::Api {
+        status: StatusCode::NOT_MODIFIED.as_u16(),
+        code: ImgapiErrorCode::Other("NotModified".into()),
+        message: "the server answered 304 for a response that is no longer cached".into(),
+    }
+}
+
+pub(crate) fn file_size(manifest: &Manifest, index: usize) -> Option<u64> {
+    manifest.files.get(index).map(|file| file.size.0)
+}
+
+pub(crate) fn expected_sha1(manifest: &Manifest, index: usize) -> Result<String> {
+    manifest
+        .files
+        .get(index)
+        .map(|file| file.sha1.to_ascii_lowercase())
+        .ok_or(ImgapiError::MissingFile {
+            uuid: manifest.uuid,
+            index,
+        })
+}
+
+pub(crate) fn verify_sha1(expected: &str, hasher: Sha1) -> Result<()> {
+    let actual = hex::encode(hasher.finalize());
+    if actual != expected {
+        return Err(ImgapiError::ChecksumMismatch {
+            expected: expected.to_string(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+//IMGAPI (restify) error bodies look like {"code": "ResourceNotFound", "message": "..."}
+#[derive(Deserialize)]
+struct ErrorBody {
+    code: String,
+    #[serde(default)]
+    message: String,
+    #[serde(default)]
+    errors: Vec<FieldError>,
+}
+
+pub(crate) fn api_error(status: u16, retry_after: Option<Duration>, body: &str) -> ImgapiError {
+    let parsed = serde_json::from_str::<ErrorBody>(body);
+    let rate_limited = status == 429 || (status == 503 && retry_after.is_some());
+    if rate_limited {
+        return ImgapiError::RateLimited {
+            status,
+            retry_after,
+            message: match parsed {
+                Ok(err) => err.message,
+                Err(_) => body.trim().to_string(),
+            },
+        };
+    }
+    match parsed {
+        Ok(err) if err.code == "ValidationFailed" => ImgapiError::ValidationFailed {
+            message: err.message,
+            errors: err.errors,
+        },
+        Ok(err) if err.code == "ImageHasDependentImages" => ImgapiError::HasDependentImages {
+            message: err.message,
+        },
+        Ok(err) => ImgapiError::Api {
+            status,
+            code: ImgapiErrorCode::from_code(&err.code),
+            message: err.message,
+        },
+        Err(_) => ImgapiError::Api {
+            status,
+            code: ImgapiErrorCode::Other(String::new()),
+            message: body.trim().to_string(),
+        },
+    }
+}
@@ -0,0 +1,94 @@
+use reqwest::header::{HeaderMap, HeaderName, HeaderValue, AUTHORIZATION};
+use reqwest::Method;
+use url::Url;
+
+use crate::error::{ImgapiError, Result};
+
+/// Adds credentials to every request a client sends.
+pub trait AuthProvider: Send + Sync + std::fmt::Debug {
+    fn authorize(&self, method: &Method, url: &Url, headers: &mut HeaderMap) -> Result<()>;
+}
+
+#[doc = "Send requests without credentials, the default"]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoAuth;
+
+impl AuthProvider for NoAuth {
+    fn authorize(&self, _method: &Method, _url: &Url, _headers: &mut HeaderMap) -> Result<()> {
+        Ok(())
+    }
+}
+
+#[doc = "Send a static token as Authorization: Bearer"]
+#[derive(Clone)]
+pub struct BearerToken(HeaderValue);
+
+impl BearerToken {
+    pub fn new(token: &str) -> Result<Self> {
+        let mut value = header_value(&AUTHORIZATION, &format!("Bearer {}", token))?;
+        value.set_sensitive(true);
+        Ok(Self(value))
+    }
+}
+
+//Never print the token itself
+impl std::fmt::Debug for BearerToken {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("BearerToken(..)")
+    }
+}
+
+impl AuthProvider for BearerToken {
+    fn authorize(&self, _method: &Method, _url: &Url, headers: &mut HeaderMap) -> Result<()> {
+        headers.insert(AUTHORIZATION, self.0.clone());
+        Ok(())
+    }
+}
+
+#[doc = "Add fixed headers, e.g. credentials for an authenticating proxy"]
+#[derive(Debug, Clone, Default)]
+pub struct HeaderAuth(HeaderMap);
+
+impl HeaderAuth {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Add a header. Values are marked sensitive and never printed.
+    pub fn header(mut self, name: &str, value: &str) -> Result<Self> {
+        let name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|_| ImgapiError::InvalidHeader(name.to_string()))?;
+        let mut value = header_value(&name, value)?;
+        value.set_sensitive(true);
+        self.0.append(name, value);
+        Ok(self)
+    }
+}
+
+impl AuthProvider for HeaderAuth {
+    fn authorize(&self, _method: &Method, _url: &Url, headers: &mut HeaderMap) -> Result<()> {
+        //Replace what the request has, but keep every value of repeated headers
+        for name in self.0.keys() {
+            let mut values = self.0.get_all(name).iter();
+            if let Some(first) = values.next() {
+                headers.insert(name, first.clone());
+            }
+            for value in values {
+                headers.append(name, value.clone());
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(feature = "signing")]
+impl<S: super::signing::Signer> AuthProvider for S {
+    fn authorize(&self, _method: &Method, _url: &Url, headers: &mut HeaderMap) -> Result<()> {
+        super::signing::sign_headers(self, headers)
+    }
+}
+
+//Errors name the header only, the value is a credential.
+fn header_value(name: &HeaderName, value: &str) -> Result<HeaderValue> {
+    HeaderValue::from_str(value).map_err(|_| ImgapiError::InvalidHeader(name.to_string()))
+}
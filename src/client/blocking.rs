@@ -0,0 +1,293 @@
+use reqwest::blocking::{RequestBuilder, Response};
+use reqwest::header::ACCEPT;
+use reqwest::{Method, StatusCode};
+use serde::de::DeserializeOwned;
+use sha1::{Digest, Sha1};
+use std::collections::VecDeque;
+use std::io::Write;
+use std::sync::Arc;
+use url::Url;
+use uuid::Uuid;
+
+use super::query::{advance_page, first_page};
+use super::{
+    api_error, expected_sha1, normalize_base_url, request, verify_sha1, AuthProvider, Channel,
+    ListImagesQuery, MetadataCache, NoAuth, RetryPolicy, ServerInfo, Throttle,
+};
+use crate::error::Result;
+use crate::manifest::Manifest;
+
+#[doc = "Synchronous counterpart of the async Client, no runtime handling needed"]
+#[derive(Debug, Clone)]
+pub struct BlockingClient {
+    base_url: Url,
+    http: reqwest::blocking::Client,
+    channel: Option<String>,
+    auth: Arc<dyn AuthProvider>,
+    retry: RetryPolicy,
+    cache: Option<Arc<MetadataCache>>,
+    throttle: Option<Throttle>,
+}
+
+impl BlockingClient {
+    pub fn new(base_url: &str) -> Result<Self> {
+        Ok(Self::with_http_client(
+            base_url.parse()?,
+            reqwest::blocking::Client::new(),
+        ))
+    }
+
+    pub fn with_http_client(base_url: Url, http: reqwest::blocking::Client) -> Self {
+        Self {
+            base_url: normalize_base_url(base_url),
+            http,
+            channel: None,
+            auth: Arc::new(NoAuth),
+            retry: RetryPolicy::none(),
+            cache: None,
+            throttle: None,
+        }
+    }
+
+    pub fn base_url(&self) -> &Url {
+        &self.base_url
+    }
+
+    /// A client whose image calls target `channel`, see Client::with_channel.
+    pub fn with_channel(&self, channel: impl Into<String>) -> Self {
+        Self {
+            channel: Some(channel.into()),
+            ..self.clone()
+        }
+    }
+
+    pub fn channel(&self) -> Option<&str> {
+        self.channel.as_deref()
+    }
+
+    /// A client that signs every request with `signer`.
+    #[cfg(feature = "signing")]
+    pub fn with_signer(&self, signer: impl super::signing::Signer + 'static) -> Self {
+        self.with_auth(signer)
+    }
+
+    /// A client that repeats requests failing with transient errors
+    /// according to `retry`.
+    pub fn with_retry(&self, retry: RetryPolicy) -> Self {
+        Self {
+            retry,
+            ..self.clone()
+        }
+    }
+
+    /// A client that answers ListImages and GetImage from `cache` when the
+    /// server reports no change, see Client::with_cache.
+    pub fn with_cache(&self, cache: Arc<MetadataCache>) -> Self {
+        Self {
+            cache: Some(cache),
+            ..self.clone()
+        }
+    }
+
+    /// A client that limits the bandwidth of image file downloads.
+    pub fn with_throttle(&self, throttle: Throttle) -> Self {
+        Self {
+            throttle: Some(throttle),
+            ..self.clone()
+        }
+    }
+
+    /// A client that lets `auth` add credentials to every request.
+    pub fn with_auth(&self, auth: impl AuthProvider + 'static) -> Self {
+        Self {
+            auth: Arc::new(auth),
+            ..self.clone()
+        }
+    }
+
+    /// Ping: check that the server is up and find out which IMGAPI version
+    /// it runs.
+    pub fn ping(&self) -> Result<ServerInfo> {
+        let req = self
+            .http
+            .get(self.url("ping")?)
+            .header(ACCEPT, "application/json");
+        json(self.send(req)?)
+    }
+
+    /// ListChannels: the channels of a channel enabled server.
+    pub fn list_channels(&self) -> Result<Vec<Channel>> {
+        let req = self
+            .http
+            .get(self.url("channels")?)
+            .header(ACCEPT, "application/json");
+        json(self.send(req)?)
+    }
+
+    /// ListImages: all active images visible to the caller.
+    pub fn list_images(&self) -> Result<Vec<Manifest>> {
+        self.list_images_with(&ListImagesQuery::default())
+    }
+
+    /// ListImages restricted by server side filters.
+    pub fn list_images_with(&self, query: &ListImagesQuery) -> Result<Vec<Manifest>> {
+        let mut query = query.clone();
+        if query.channel.is_none() {
+            query.channel.clone_from(&self.channel);
+        }
+        let req = self.http.get(self.url("images")?).query(&query.to_query());
+        self.get_json(req)
+    }
+
+    /// ListImages following limit/marker paging until all matching images
+    /// are returned. The query's limit is used as the page size.
+    pub fn list_images_paged(&self, query: ListImagesQuery) -> ListImagesPaged<'_> {
+        ListImagesPaged {
+            client: self,
+            next: Some(first_page(query)),
+            page: VecDeque::new(),
+        }
+    }
+
+    /// GetImage: a single image by UUID.
+    pub fn get_image(&self, uuid: Uuid) -> Result<Manifest> {
+        let req = self.request(Method::GET, &format!("images/{}", uuid))?;
+        self.get_json(req)
+    }
+
+    /// GetImageFile: write file `index` of the image to `writer` while
+    /// verifying it against the SHA-1 recorded in the manifest. Returns the
+    /// number of bytes written.
+    pub fn get_image_file<W: Write>(
+        &self,
+        uuid: Uuid,
+        index: usize,
+        writer: &mut W,
+    ) -> Result<u64> {
+        let expected = expected_sha1(&self.get_image(uuid)?, index)?;
+
+        let url = request::image_url(
+            &self.base_url,
+            &format!("images/{}/file", uuid),
+            self.channel.as_deref(),
+        )?;
+        let req = self.http.get(url).query(&[("index", index)]);
+        let mut resp = self.send(req)?;
+
+        let mut writer = HashingWriter {
+            inner: writer,
+            hasher: Sha1::new(),
+            throttle: self.throttle.as_ref(),
+        };
+        let written = resp.copy_to(&mut writer)?;
+        writer.flush()?;
+
+        verify_sha1(&expected, writer.hasher)?;
+        Ok(written)
+    }
+
+    fn url(&self, path: &str) -> Result<Url> {
+        Ok(self.base_url.join(path)?)
+    }
+
+    //A request to a JSON image endpoint.
+    fn request(&self, method: Method, path: &str) -> Result<RequestBuilder> {
+        let url = request::image_url(&self.base_url, path, self.channel.as_deref())?;
+        Ok(self.http.request(method, url).header(ACCEPT, request::JSON))
+    }
+
+    fn get_json<T: DeserializeOwned>(&self, req: RequestBuilder) -> Result<T> {
+        let Some(cache) = &self.cache else {
+            return json(self.send(req)?);
+        };
+        let mut req = req.build()?;
+        let url = req.url().clone();
+        let key = request::add_cache_conditions(cache, &url, req.headers_mut());
+        let resp = self.send_request(req)?;
+        let headers = resp.headers().clone();
+        let fetched = match resp.status() {
+            StatusCode::NOT_MODIFIED => None,
+            _ => Some(resp.bytes()?.to_vec()),
+        };
+        let body = request::cached_body(cache, &key, &headers, fetched)?;
+        Ok(serde_json::from_slice(&body)?)
+    }
+
+    fn send(&self, req: RequestBuilder) -> Result<Response> {
+        self.send_request(req.build()?)
+    }
+
+    fn send_request(&self, mut req: reqwest::blocking::Request) -> Result<Response> {
+        let mut attempt = 1;
+        let resp = loop {
+            let next = request::prepare_attempt(&self.retry, Some(&*self.auth), &mut req, attempt)?;
+            let result = self.http.execute(req);
+            let outcome = result.as_ref().map(|resp| (resp.status(), resp.headers()));
+            match (next, request::retry_delay(&self.retry, outcome, attempt)) {
+                (Some(next), Some(delay)) => {
+                    std::thread::sleep(delay);
+                    req = next;
+                    attempt += 1;
+                }
+                _ => break result?,
+            }
+        };
+        match request::failure(resp.status(), resp.headers()) {
+            None => Ok(resp),
+            Some((status, retry_after)) => {
+                let body = resp.text().unwrap_or_default();
+                Err(api_error(status, retry_after, &body))
+            }
+        }
+    }
+}
+
+fn json<T: DeserializeOwned>(resp: Response) -> Result<T> {
+    let body = resp.bytes()?;
+    Ok(serde_json::from_slice(&body)?)
+}
+
+struct HashingWriter<'a, W> {
+    inner: &'a mut W,
+    hasher: Sha1,
+    throttle: Option<&'a Throttle>,
+}
+
+impl<W: Write> Write for HashingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(&buf[..n]);
+        if let Some(throttle) = self.throttle {
+            throttle.acquire_blocking(n as u64);
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[doc = "Iterator returned by BlockingClient::list_images_paged"]
+pub struct ListImagesPaged<'a> {
+    client: &'a BlockingClient,
+    next: Option<ListImagesQuery>,
+    page: VecDeque<Manifest>,
+}
+
+impl Iterator for ListImagesPaged<'_> {
+    type Item = Result<Manifest>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while self.page.is_empty() {
+            let query = self.next.take()?;
+            let mut page = match self.client.list_images_with(&query) {
+                Ok(page) => page,
+                Err(err) => return Some(Err(err)),
+            };
+            self.next = advance_page(query, &mut page);
+            self.page = page.into();
+        }
+        self.page.pop_front().map(Ok)
+    }
+}
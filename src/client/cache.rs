@@ -0,0 +1,77 @@
+use reqwest::header::{
+    HeaderMap, HeaderValue, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED,
+};
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+#[doc = "Remembers ListImages/GetImage responses with their validators to send conditional requests"]
+#[derive(Debug, Default)]
+pub struct MetadataCache {
+    //Keyed by the full request URL, query included.
+    entries: Mutex<HashMap<String, CachedResponse>>,
+}
+
+#[derive(Debug, Clone)]
+struct CachedResponse {
+    etag: Option<HeaderValue>,
+    last_modified: Option<HeaderValue>,
+    body: Vec<u8>,
+}
+
+impl MetadataCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn len(&self) -> usize {
+        self.lock().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lock().is_empty()
+    }
+
+    pub fn clear(&self) {
+        self.lock().clear()
+    }
+
+    /// Add If-None-Match/If-Modified-Since for a cached response to `key`.
+    pub(crate) fn add_conditions(&self, key: &str, headers: &mut HeaderMap) {
+        if let Some(entry) = self.lock().get(key) {
+            if let Some(etag) = &entry.etag {
+                headers.insert(IF_NONE_MATCH, etag.clone());
+            }
+            if let Some(last_modified) = &entry.last_modified {
+                headers.insert(IF_MODIFIED_SINCE, last_modified.clone());
+            }
+        }
+    }
+
+    /// Remember `body` if the response carries a validator.
+    pub(crate) fn store(&self, key: &str, headers: &HeaderMap, body: &[u8]) {
+        let etag = headers.get(ETAG).cloned();
+        let last_modified = headers.get(LAST_MODIFIED).cloned();
+        let mut entries = self.lock();
+        if etag.is_none() && last_modified.is_none() {
+            entries.remove(key);
+            return;
+        }
+        entries.insert(
+            key.to_string(),
+            CachedResponse {
+                etag,
+                last_modified,
+                body: body.to_vec(),
+            },
+        );
+    }
+
+    pub(crate) fn body(&self, key: &str) -> Option<Vec<u8>> {
+        self.lock().get(key).map(|entry| entry.body.clone())
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, HashMap<String, CachedResponse>> {
+        //The map stays consistent even if a holder panicked
+        self.entries.lock().unwrap_or_else(|e| e.into_inner())
+    }
+}
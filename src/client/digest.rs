@@ -0,0 +1,126 @@
+use base64::Engine;
+use md5::Md5;
+use sha1::{Digest, Sha1};
+use sha2::Sha256;
+use std::io::Read;
+use std::path::Path;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tokio::io::{AsyncRead, AsyncReadExt, ReadBuf};
+
+use crate::error::Result;
+
+#[doc = "Computes SHA-1, SHA-256 and MD5 of a stream in a single pass"]
+#[derive(Debug, Clone, Default)]
+pub struct MultiDigest {
+    sha1: Sha1,
+    sha256: Sha256,
+    md5: Md5,
+    len: u64,
+}
+
+#[doc = "The digests of a file, as computed by MultiDigest"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Digests {
+    pub sha1: [u8; 20],
+    pub sha256: [u8; 32],
+    pub md5: [u8; 16],
+    pub len: u64,
+}
+
+impl MultiDigest {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn update(&mut self, data: &[u8]) {
+        self.sha1.update(data);
+        self.sha256.update(data);
+        self.md5.update(data);
+        self.len += data.len() as u64;
+    }
+
+    pub fn finalize(self) -> Digests {
+        Digests {
+            sha1: self.sha1.finalize().into(),
+            sha256: self.sha256.finalize().into(),
+            md5: self.md5.finalize().into(),
+            len: self.len,
+        }
+    }
+}
+
+impl Digests {
+    /// Read the file at `path` once for all digests.
+    pub async fn from_path(path: impl AsRef<Path>) -> Result<Self> {
+        let mut reader = DigestReader::new(tokio::fs::File::open(path).await?);
+        let mut buf = vec![0u8; 64 * 1024];
+        while reader.read(&mut buf).await? > 0 {}
+        Ok(reader.finalize())
+    }
+
+    /// The form of the manifest's files[].sha1.
+    pub fn sha1_hex(&self) -> String {
+        hex::encode(self.sha1)
+    }
+
+    pub fn sha256_hex(&self) -> String {
+        hex::encode(self.sha256)
+    }
+
+    /// The form of the Content-MD5 header, base64 of the raw digest.
+    pub fn content_md5(&self) -> String {
+        base64::engine::general_purpose::STANDARD.encode(self.md5)
+    }
+}
+
+/// A reader that feeds everything read through it into a [`MultiDigest`],
+/// for both std and tokio readers. Wrap the source of an upload in it to
+/// get the digests of exactly the bytes that were sent.
+#[derive(Debug)]
+pub struct DigestReader<R> {
+    inner: R,
+    digest: MultiDigest,
+}
+
+impl<R> DigestReader<R> {
+    pub fn new(inner: R) -> Self {
+        Self {
+            inner,
+            digest: MultiDigest::new(),
+        }
+    }
+
+    /// The digests of the data read so far.
+    pub fn digests(&self) -> Digests {
+        self.digest.clone().finalize()
+    }
+
+    pub fn finalize(self) -> Digests {
+        self.digest.finalize()
+    }
+}
+
+impl<R: Read> Read for DigestReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.digest.update(&buf[..n]);
+        Ok(n)
+    }
+}
+
+impl<R: AsyncRead + Unpin> AsyncRead for DigestReader<R> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<std::io::Result<()>> {
+        let before = buf.filled().len();
+        let poll = Pin::new(&mut self.inner).poll_read(cx, buf);
+        if let Poll::Ready(Ok(())) = &poll {
+            let read = &buf.filled()[before..];
+            self.digest.update(read);
+        }
+        poll
+    }
+}
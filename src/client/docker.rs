@@ -0,0 +1,131 @@
+use derive_builder::Builder;
+use futures::{Stream, StreamExt};
+use reqwest::Response;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use super::json_stream::ndjson;
+use super::ParamsBuilderError;
+use crate::error::{ImgapiError, Result};
+use crate::manifest::Manifest;
+
+#[doc = "Parameters for AdminImportDockerImage"]
+#[derive(Debug, Clone, Builder)]
+#[builder(build_fn(error = "ParamsBuilderError"))]
+pub struct DockerImportOptions {
+    //Repository to pull, e.g. busybox or docker.io/library/busybox.
+    #[builder(setter(into))]
+    pub repo: String,
+
+    //Tag to pull, the registry default is used when unset.
+    #[builder(setter(into, strip_option), default)]
+    pub tag: Option<String>,
+
+    //Pull by digest instead of tag.
+    #[builder(setter(into, strip_option), default)]
+    pub digest: Option<String>,
+
+    #[builder(default = "true")]
+    pub public: bool,
+
+    //Base64 encoded registry credentials, sent as the X-Registry-Auth header.
+    #[builder(setter(into, strip_option), default)]
+    pub registry_auth: Option<String>,
+}
+
+impl DockerImportOptions {
+    pub fn to_query(&self) -> Vec<(String, String)> {
+        let mut query = vec![
+            ("action".to_string(), "import-docker-image".to_string()),
+            ("repo".to_string(), self.repo.clone()),
+            ("public".to_string(), self.public.to_string()),
+        ];
+        if let Some(tag) = &self.tag {
+            query.push(("tag".into(), tag.clone()));
+        }
+        if let Some(digest) = &self.digest {
+            query.push(("digest".into(), digest.clone()));
+        }
+        query
+    }
+}
+
+#[doc = "A progress message of AdminImportDockerImage"]
+#[derive(Debug, Clone, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase")]
+pub enum DockerImportEvent {
+    //The image being imported, sent first.
+    Head {
+        id: String,
+        head: Uuid,
+    },
+    Status {
+        #[serde(default)]
+        id: Option<String>,
+        payload: DockerProgress,
+    },
+    Progress {
+        #[serde(default)]
+        id: Option<String>,
+        payload: DockerProgress,
+    },
+    //A layer that was imported as an image.
+    Data {
+        #[serde(default)]
+        id: Option<String>,
+        image: Box<Manifest>,
+    },
+    #[serde(other)]
+    Unknown,
+}
+
+#[doc = "Docker style pull progress, as shown by docker pull"]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "camelCase")]
+pub struct DockerProgress {
+    #[serde(default)]
+    pub status: String,
+    //The layer the message is about.
+    #[serde(default)]
+    pub id: Option<String>,
+    #[serde(default)]
+    pub progress_detail: Option<ProgressDetail>,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, PartialEq, Eq)]
+pub struct ProgressDetail {
+    #[serde(default)]
+    pub current: u64,
+    #[serde(default)]
+    pub total: u64,
+}
+
+//Error messages end the import, {"type": "error", "error": {"code": ..., "message": ...}}
+#[derive(Deserialize)]
+struct ErrorEvent {
+    error: ErrorDetail,
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    #[serde(default)]
+    code: String,
+    #[serde(default)]
+    message: String,
+}
+
+pub(crate) fn import_events(resp: Response) -> impl Stream<Item = Result<DockerImportEvent>> {
+    let status = resp.status().as_u16();
+    ndjson::<serde_json::Value>(resp).map(move |line| {
+        let line = line?;
+        if line.get("type").and_then(|t| t.as_str()) == Some("error") {
+            let ErrorEvent { error } = serde_json::from_value(line)?;
+            return Err(ImgapiError::Api {
+                status,
+                code: crate::error::ImgapiErrorCode::from_code(&error.code),
+                message: error.message,
+            });
+        }
+        Ok(serde_json::from_value(line)?)
+    })
+}
@@ -0,0 +1,357 @@
+use futures::{stream, StreamExt, TryStreamExt};
+use sha1::{Digest, Sha1};
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use uuid::Uuid;
+
+use super::{file_size, verify_sha1, Client};
+use crate::error::{ImgapiError, Result};
+use crate::id::ImageId;
+use crate::manifest::Manifest;
+#[cfg(feature = "store")]
+use crate::store::{Store, StoreLock};
+
+type ProgressFn = dyn Fn(DownloadProgress) + Send + Sync;
+
+#[doc = "Downloads the files of a set of images and the images they are based on"]
+#[derive(Clone)]
+pub struct Downloader {
+    client: Client,
+    dir: PathBuf,
+    //Files downloaded at the same time.
+    concurrency: usize,
+    //Images present locally, their origin chains are not followed.
+    exclude: HashSet<Uuid>,
+    progress: Option<Arc<ProgressFn>>,
+    //Files present here are not downloaded again, see with_store.
+    #[cfg(feature = "store")]
+    store: Option<Store>,
+}
+
+impl std::fmt::Debug for Downloader {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Downloader")
+            .field("client", &self.client)
+            .field("dir", &self.dir)
+            .field("concurrency", &self.concurrency)
+            .field("exclude", &self.exclude)
+            .finish_non_exhaustive()
+    }
+}
+
+#[doc = "Progress of one file of a Downloader run, with the totals of the run"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DownloadProgress {
+    pub image: Uuid,
+    pub index: usize,
+    pub file_bytes: u64,
+    //From the manifest, 0 if it has no size.
+    pub file_size: u64,
+    pub total_bytes: u64,
+    pub total_size: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct DownloadedImage {
+    pub manifest: Manifest,
+    //One path per entry of manifest.files.
+    pub files: Vec<PathBuf>,
+}
+
+impl Downloader {
+    /// Download into `dir`, which must exist, four files at a time.
+    pub fn new(client: Client, dir: impl Into<PathBuf>) -> Self {
+        Self {
+            client,
+            dir: dir.into(),
+            concurrency: 4,
+            exclude: HashSet::new(),
+            progress: None,
+            #[cfg(feature = "store")]
+            store: None,
+        }
+    }
+
+    pub fn concurrency(mut self, concurrency: usize) -> Self {
+        self.concurrency = concurrency.max(1);
+        self
+    }
+
+    /// Skip these images, e.g. because they are already installed, and stop
+    /// resolving origin chains at them.
+    pub fn exclude(mut self, uuids: impl IntoIterator<Item = Uuid>) -> Self {
+        self.exclude.extend(uuids);
+        self
+    }
+
+    /// Call `progress` after every chunk received for any file.
+    pub fn on_progress(
+        mut self,
+        progress: impl Fn(DownloadProgress) + Send + Sync + 'static,
+    ) -> Self {
+        self.progress = Some(Arc::new(progress));
+        self
+    }
+
+    /// Take files from `store` when it has them by SHA-1, after checking
+    /// their content, and move downloaded files into it. The target
+    /// directory then only holds partial downloads and the returned paths
+    /// point into the store, which also receives the manifests.
+    #[cfg(feature = "store")]
+    pub fn with_store(mut self, store: Store) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// The manifests of the images `ids` refer to and of their origins,
+    /// origins first and each image only once.
+    pub async fn resolve<I: Into<ImageId> + Clone>(&self, ids: &[I]) -> Result<Vec<Manifest>> {
+        let mut seen = self.exclude.clone();
+        let mut resolved = vec![];
+        for id in ids {
+            let mut manifest = match id.clone().into() {
+                ImageId::Uuid(uuid) if seen.contains(&uuid) => continue,
+                id => self.client.resolve(&id).await?,
+            };
+            let mut chain = vec![];
+            //Also ends an origin loop
+            while seen.insert(manifest.uuid) {
+                let origin = manifest.origin.filter(|origin| !seen.contains(origin));
+                chain.push(manifest);
+                match origin {
+                    Some(origin) => manifest = self.client.get_image(origin).await?,
+                    None => break,
+                }
+            }
+            resolved.extend(chain.into_iter().rev());
+        }
+        Ok(resolved)
+    }
+
+    /// Resolve `ids` and download all their files into the target
+    /// directory as `{uuid}.{index}.file`. Each file is verified against its
+    /// SHA-1 and only renamed into place once complete; files finished in an
+    /// earlier run are kept and interrupted ones are resumed. With a store,
+    /// the manifests are put once all files are in.
+    pub async fn download<I: Into<ImageId> + Clone>(
+        &self,
+        ids: &[I],
+    ) -> Result<Vec<DownloadedImage>> {
+        let manifests = self.resolve(ids).await?;
+
+        let jobs: Vec<(usize, usize)> = manifests
+            .iter()
+            .enumerate()
+            .flat_map(|(image, m)| (0..m.files.len()).map(move |index| (image, index)))
+            .collect();
+        let total_size = jobs
+            .iter()
+            .map(|(image, index)| file_size(&manifests[*image], *index).unwrap_or(0))
+            .sum();
+        let total_bytes = Arc::new(AtomicU64::new(0));
+
+        let mut paths: Vec<Vec<PathBuf>> = manifests.iter().map(|_| vec![]).collect();
+        //Held until the manifests are put after their files, so gc does not
+        //remove files imported for a manifest not written yet
+        #[cfg(feature = "store")]
+        let _locks = match &self.store {
+            Some(store) => lock_images(store, &manifests).await?,
+            None => vec![],
+        };
+
+        let mut done: Vec<(usize, usize, PathBuf)> = stream::iter(jobs)
+            .map(|(image, index)| {
+                let manifest = &manifests[image];
+                let total_bytes = total_bytes.clone();
+                async move {
+                    let path = self
+                        .fetch(manifest, index, total_size, &total_bytes)
+                        .await?;
+                    Ok::<_, ImgapiError>((image, index, path))
+                }
+            })
+            .buffer_unordered(self.concurrency)
+            .try_collect()
+            .await?;
+        done.sort_by_key(|(image, index, _)| (*image, *index));
+        for (image, _, path) in done {
+            paths[image].push(path);
+        }
+        //Only once all files are in, a failed run leaves no manifest without
+        //its files behind
+        #[cfg(feature = "store")]
+        let manifests = match &self.store {
+            Some(store) => {
+                let store = store.clone();
+                blocking(move || {
+                    for manifest in &manifests {
+                        store.put_manifest(manifest)?;
+                    }
+                    Ok(manifests)
+                })
+                .await?
+            }
+            None => manifests,
+        };
+
+        Ok(manifests
+            .into_iter()
+            .zip(paths)
+            .map(|(manifest, files)| DownloadedImage { manifest, files })
+            .collect())
+    }
+
+    async fn fetch(
+        &self,
+        manifest: &Manifest,
+        index: usize,
+        total_size: u64,
+        total_bytes: &AtomicU64,
+    ) -> Result<PathBuf> {
+        let path = self.dir.join(format!("{}.{}.file", manifest.uuid, index));
+        let partial = part_path(&path);
+        let file_size = file_size(manifest, index).unwrap_or(0);
+
+        let mut last = 0;
+        let mut report = |file_bytes: u64| {
+            let received = file_bytes - last;
+            last = file_bytes;
+            let total = total_bytes.fetch_add(received, Ordering::Relaxed) + received;
+            if let Some(progress) = &self.progress {
+                progress(DownloadProgress {
+                    image: manifest.uuid,
+                    index,
+                    file_bytes,
+                    file_size,
+                    total_bytes: total,
+                    total_size,
+                });
+            }
+        };
+        #[cfg(feature = "store")]
+        let stored = match &self.store {
+            Some(store) => Some((store, super::expected_sha1(manifest, index)?)),
+            None => None,
+        };
+        #[cfg(feature = "store")]
+        if let Some((store, sha1)) = &stored {
+            if let Some(path) = cached(store, sha1).await? {
+                report(tokio::fs::metadata(&path).await?.len());
+                return Ok(path);
+            }
+        }
+
+        //Finished in an earlier run
+        if finished(&path, super::expected_sha1(manifest, index)?).await? {
+            report(tokio::fs::metadata(&path).await?.len());
+            #[cfg(feature = "store")]
+            if let Some((store, sha1)) = stored {
+                let store = store.clone();
+                return blocking(move || store.import_file(&sha1, &path)).await;
+            }
+            return Ok(path);
+        }
+
+        //A partial file left by an interrupted run is resumed
+        let fetched = self
+            .client
+            .fetch_file_resume(manifest, index, &partial, &mut report)
+            .await;
+        if let Err(e) = fetched {
+            //Corrupt content would only fail again, a broken connection can resume
+            if matches!(e, ImgapiError::ChecksumMismatch { .. }) {
+                let _ = tokio::fs::remove_file(&partial).await;
+            }
+            return Err(e);
+        }
+        #[cfg(feature = "store")]
+        if let Some((store, sha1)) = stored {
+            let store = store.clone();
+            return blocking(move || store.import_file(&sha1, &partial)).await;
+        }
+        tokio::fs::rename(&partial, &path).await?;
+        Ok(path)
+    }
+}
+
+//The stored file with `sha1`, if it is intact. Corrupt files are removed
+//so they are downloaded again.
+#[cfg(feature = "store")]
+async fn cached(store: &Store, sha1: &str) -> Result<Option<PathBuf>> {
+    if !store.has_file(sha1) {
+        return Ok(None);
+    }
+    let (store, sha1) = (store.clone(), sha1.to_string());
+    blocking(move || match store.verify_file(&sha1) {
+        Ok(()) => Ok(Some(store.file_path(&sha1)?)),
+        Err(ImgapiError::ChecksumMismatch { actual, .. }) => {
+            log::warn!(
+                "stored file {} is corrupt ({}), downloading it again",
+                sha1,
+                actual
+            );
+            store.remove_file(&sha1)?;
+            Ok(None)
+        }
+        Err(e) => Err(e),
+    })
+    .await
+}
+
+//Lock the images in UUID order, so downloaders sharing images cannot
+//deadlock. Waiting for a lock would stall the runtime.
+#[cfg(feature = "store")]
+async fn lock_images(store: &Store, manifests: &[Manifest]) -> Result<Vec<StoreLock>> {
+    let mut uuids: Vec<Uuid> = manifests.iter().map(|m| m.uuid).collect();
+    uuids.sort();
+    let store = store.clone();
+    blocking(move || {
+        uuids
+            .into_iter()
+            .map(|uuid| store.lock_image(uuid))
+            .collect()
+    })
+    .await
+}
+
+//Whether `path` holds the file with `sha1`. Corrupt files are removed so
+//they are downloaded again.
+async fn finished(path: &Path, sha1: String) -> Result<bool> {
+    if !tokio::fs::try_exists(path).await? {
+        return Ok(false);
+    }
+    let path = path.to_path_buf();
+    blocking(move || {
+        let mut hasher = Sha1::new();
+        std::io::copy(&mut std::fs::File::open(&path)?, &mut hasher)?;
+        match verify_sha1(&sha1, hasher) {
+            Ok(()) => Ok(true),
+            Err(ImgapiError::ChecksumMismatch { actual, .. }) => {
+                log::warn!(
+                    "{} is corrupt ({}), downloading it again",
+                    path.display(),
+                    actual
+                );
+                std::fs::remove_file(&path)?;
+                Ok(false)
+            }
+            Err(e) => Err(e),
+        }
+    })
+    .await
+}
+
+//Hashing whole image files would stall the runtime.
+async fn blocking<T: Send + 'static>(f: impl FnOnce() -> Result<T> + Send + 'static) -> Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| ImgapiError::Io(std::io::Error::other(e)))?
+}
+
+fn part_path(path: &Path) -> PathBuf {
+    let mut partial = path.as_os_str().to_owned();
+    partial.push(".part");
+    PathBuf::from(partial)
+}
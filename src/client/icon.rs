@@ -0,0 +1,37 @@
+use strum::{Display as StrumDisplay, EnumString};
+
+//IMGAPI rejects icons larger than this.
+pub const MAX_ICON_SIZE: usize = 128 * 1024;
+
+#[doc = "Image formats IMGAPI accepts for image icons"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, StrumDisplay, EnumString)]
+pub enum IconType {
+    #[strum(serialize = "image/png")]
+    Png,
+    #[strum(serialize = "image/jpeg")]
+    Jpeg,
+    #[strum(serialize = "image/gif")]
+    Gif,
+}
+
+impl IconType {
+    /// The icon format indicated by the magic bytes at the start of `data`.
+    pub fn sniff(data: &[u8]) -> Option<Self> {
+        match data {
+            [0x89, b'P', b'N', b'G', ..] => Some(IconType::Png),
+            [0xff, 0xd8, 0xff, ..] => Some(IconType::Jpeg),
+            [b'G', b'I', b'F', b'8', ..] => Some(IconType::Gif),
+            _ => None,
+        }
+    }
+
+    pub fn mime_type(&self) -> &'static str {
+        match self {
+            IconType::Png => "image/png",
+            IconType::Jpeg => "image/jpeg",
+            IconType::Gif => "image/gif",
+        }
+    }
+}
+
+pub(crate) const ACCEPT_ICON: &str = "image/png, image/jpeg, image/gif";
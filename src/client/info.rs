@@ -0,0 +1,50 @@
+use serde::Deserialize;
+
+//First IMGAPI releases shipping the feature.
+const CHANNELS_SINCE: (u64, u64, u64) = (2, 1, 0);
+const DOCKER_SINCE: (u64, u64, u64) = (2, 2, 0);
+
+#[doc = "The response of Ping, identifying the server"]
+#[derive(Debug, Clone, Deserialize, PartialEq, Eq)]
+pub struct ServerInfo {
+    //Always "pong".
+    pub ping: String,
+    #[serde(default)]
+    pub pid: Option<u32>,
+    //Version of the IMGAPI server, not sent by very old servers.
+    #[serde(default)]
+    pub version: Option<String>,
+    //Set by IMGAPI to tell it apart from other services answering /ping.
+    #[serde(default)]
+    pub imgapi: bool,
+}
+
+impl ServerInfo {
+    /// The server version as (major, minor, patch), ignoring any
+    /// pre-release or build suffix.
+    pub fn version_triple(&self) -> Option<(u64, u64, u64)> {
+        let version = self.version.as_deref()?;
+        let core = version.split(['-', '+']).next()?;
+        let mut parts = core.split('.').map(|p| p.parse::<u64>().ok());
+        Some((
+            parts.next()??,
+            parts.next().flatten()?,
+            parts.next().flatten().unwrap_or(0),
+        ))
+    }
+
+    /// Whether the server understands ListChannels and the channel
+    /// parameter. Servers may still run with channels disabled.
+    pub fn supports_channels(&self) -> bool {
+        self.at_least(CHANNELS_SINCE)
+    }
+
+    /// Whether the server has AdminImportDockerImage.
+    pub fn supports_docker(&self) -> bool {
+        self.at_least(DOCKER_SINCE)
+    }
+
+    fn at_least(&self, since: (u64, u64, u64)) -> bool {
+        self.imgapi && self.version_triple().is_some_and(|v| v >= since)
+    }
+}
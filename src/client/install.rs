@@ -0,0 +1,96 @@
+use bzip2::write::BzDecoder;
+use flate2::write::GzDecoder;
+use std::io::{Error as IoError, ErrorKind, Write};
+use std::process::{Child, ChildStdin, Command, Stdio};
+
+use crate::error::{ImgapiError, Result};
+use crate::manifest::ImageFileCompression;
+
+//Decompresses whatever is written to it into the sink.
+pub(crate) enum Decoder<W: Write> {
+    Gzip(GzDecoder<W>),
+    Bzip2(BzDecoder<W>),
+    None(W),
+}
+
+impl<W: Write> Decoder<W> {
+    pub(crate) fn new(compression: &ImageFileCompression, sink: W) -> Self {
+        match compression {
+            ImageFileCompression::Gzip => Decoder::Gzip(GzDecoder::new(sink)),
+            ImageFileCompression::Bzip2 => Decoder::Bzip2(BzDecoder::new(sink)),
+            ImageFileCompression::None => Decoder::None(sink),
+        }
+    }
+
+    pub(crate) fn write_all(&mut self, data: &[u8]) -> std::io::Result<()> {
+        match self {
+            Decoder::Gzip(decoder) => decoder.write_all(data),
+            Decoder::Bzip2(decoder) => decoder.write_all(data),
+            Decoder::None(sink) => sink.write_all(data),
+        }
+    }
+
+    /// Write out the rest of the decompressed data and return the sink.
+    pub(crate) fn finish(self) -> std::io::Result<W> {
+        let mut sink = match self {
+            Decoder::Gzip(decoder) => decoder.finish()?,
+            Decoder::Bzip2(mut decoder) => decoder.finish()?,
+            Decoder::None(sink) => sink,
+        };
+        sink.flush()?;
+        Ok(sink)
+    }
+}
+
+#[doc = "A zfs receive child process, usable as the sink of Client::install_image_file"]
+#[derive(Debug)]
+pub struct ZfsReceive {
+    child: Child,
+    stdin: Option<ChildStdin>,
+}
+
+impl ZfsReceive {
+    /// Run `zfs receive <dataset>`.
+    pub fn new(dataset: &str) -> Result<Self> {
+        let mut command = Command::new("zfs");
+        command.arg("receive").arg(dataset);
+        Self::spawn(command)
+    }
+
+    /// Run a custom receive command, e.g. with -F or through pfexec. Its
+    /// stdin is replaced by the image stream.
+    pub fn spawn(mut command: Command) -> Result<Self> {
+        let mut child = command.stdin(Stdio::piped()).spawn()?;
+        let stdin = child.stdin.take();
+        Ok(Self { child, stdin })
+    }
+
+    /// Close the stream and wait for the command, failing if it did.
+    pub fn wait(mut self) -> Result<()> {
+        drop(self.stdin.take());
+        let status = self.child.wait()?;
+        if !status.success() {
+            return Err(ImgapiError::Io(IoError::other(format!(
+                "zfs receive failed: {}",
+                status
+            ))));
+        }
+        Ok(())
+    }
+}
+
+impl Write for ZfsReceive {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match &mut self.stdin {
+            Some(stdin) => stdin.write(buf),
+            None => Err(ErrorKind::BrokenPipe.into()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match &mut self.stdin {
+            Some(stdin) => stdin.flush(),
+            None => Ok(()),
+        }
+    }
+}
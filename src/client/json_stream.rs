@@ -0,0 +1,126 @@
+use futures::{stream, Stream, StreamExt};
+use reqwest::Response;
+use serde::de::DeserializeOwned;
+use std::io::{Error as IoError, ErrorKind};
+
+use crate::error::{ImgapiError, Result};
+
+/// Parse a response body holding a JSON array, yielding the elements as
+/// they arrive instead of buffering the whole array.
+pub(crate) fn json_array<T: DeserializeOwned>(resp: Response) -> impl Stream<Item = Result<T>> {
+    let body = resp.bytes_stream().boxed();
+    stream::try_unfold(
+        (body, ArraySplitter::default()),
+        |(mut body, mut splitter)| async move {
+            loop {
+                if let Some(element) = splitter.next_element()? {
+                    let item = serde_json::from_slice(&element)?;
+                    return Ok(Some((item, (body, splitter))));
+                }
+                if splitter.done {
+                    return Ok::<_, ImgapiError>(None);
+                }
+                match body.next().await {
+                    Some(chunk) => splitter.buf.extend_from_slice(&chunk?),
+                    None => return Err(invalid("truncated JSON array")),
+                }
+            }
+        },
+    )
+}
+
+/// Parse a newline delimited JSON response body as it arrives.
+pub(crate) fn ndjson<T: DeserializeOwned>(resp: Response) -> impl Stream<Item = Result<T>> {
+    let body = resp.bytes_stream().boxed();
+    stream::try_unfold(
+        (body, Vec::new(), false),
+        |(mut body, mut buf, mut done)| async move {
+            loop {
+                let line_end = buf.iter().position(|b| *b == b'\n');
+                let line: Vec<u8> = match line_end {
+                    Some(pos) => buf.drain(..=pos).collect(),
+                    //The last line need not end with a newline
+                    None if done => std::mem::take(&mut buf),
+                    None => {
+                        match body.next().await {
+                            Some(chunk) => buf.extend_from_slice(&chunk?),
+                            None => done = true,
+                        }
+                        continue;
+                    }
+                };
+                if !line.trim_ascii().is_empty() {
+                    let item = serde_json::from_slice(&line)?;
+                    return Ok(Some((item, (body, buf, done))));
+                }
+                if done && buf.is_empty() {
+                    return Ok::<_, ImgapiError>(None);
+                }
+            }
+        },
+    )
+}
+
+//Finds the boundaries of top level array elements, only tracking nesting
+//and strings; the elements themselves are checked by serde_json.
+#[derive(Debug, Default)]
+struct ArraySplitter {
+    buf: Vec<u8>,
+    //Scan position in buf, everything before it belongs to the current element.
+    pos: usize,
+    depth: usize,
+    in_string: bool,
+    escape: bool,
+    done: bool,
+}
+
+impl ArraySplitter {
+    fn next_element(&mut self) -> Result<Option<Vec<u8>>> {
+        while !self.done && self.pos < self.buf.len() {
+            let byte = self.buf[self.pos];
+            self.pos += 1;
+            if self.in_string {
+                match byte {
+                    _ if self.escape => self.escape = false,
+                    b'\\' => self.escape = true,
+                    b'"' => self.in_string = false,
+                    _ => {}
+                }
+                continue;
+            }
+            match byte {
+                b'[' if self.depth == 0 => {
+                    self.depth = 1;
+                    self.take();
+                }
+                _ if self.depth == 0 && !byte.is_ascii_whitespace() => {
+                    return Err(invalid("expected a JSON array"));
+                }
+                b'"' => self.in_string = true,
+                b'[' | b'{' => self.depth += 1,
+                b',' if self.depth == 1 => return Ok(Some(self.take())),
+                b']' if self.depth == 1 => {
+                    self.done = true;
+                    let element = self.take();
+                    //Only the empty array has no element before the bracket
+                    return Ok((!element.trim_ascii().is_empty()).then_some(element));
+                }
+                b']' | b'}' => self.depth -= 1,
+                _ => {}
+            }
+        }
+        Ok(None)
+    }
+
+    //Remove the scanned bytes from buf, returning them without the delimiter.
+    fn take(&mut self) -> Vec<u8> {
+        let mut element: Vec<u8> = self.buf.drain(..self.pos).collect();
+        element.pop();
+        self.pos = 0;
+        element
+    }
+}
+
+fn invalid(message: &str) -> ImgapiError {
+    ImgapiError::Io(IoError::new(ErrorKind::InvalidData, message))
+}
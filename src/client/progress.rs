@@ -0,0 +1,125 @@
+use std::fmt::Debug;
+use uuid::Uuid;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferPhase {
+    Downloading,
+    Uploading,
+    //All bytes are transferred, the SHA-1 is checked locally (downloads) or
+    //by the server (uploads).
+    Verifying,
+    Finished,
+}
+
+#[doc = "The state of one image file transfer, passed to a ProgressObserver"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Transfer {
+    pub image: Uuid,
+    pub index: usize,
+    pub phase: TransferPhase,
+    pub transferred: u64,
+    //From the manifest or AddImageFileParams.size, if known.
+    pub total: Option<u64>,
+}
+
+/// Receives updates on image file downloads and uploads of a client, see
+/// `Client::with_progress`. Called from the transferring task after every
+/// chunk, so implementations should return quickly.
+pub trait ProgressObserver: Send + Sync + Debug {
+    fn update(&self, transfer: &Transfer);
+}
+
+#[doc = "Ignores all progress updates, the default"]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct NoProgress;
+
+impl ProgressObserver for NoProgress {
+    fn update(&self, _transfer: &Transfer) {}
+}
+
+#[cfg(feature = "indicatif")]
+pub use bars::IndicatifProgress;
+
+#[cfg(feature = "indicatif")]
+mod bars {
+    use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+    use std::collections::HashMap;
+    use std::sync::Mutex;
+    use uuid::Uuid;
+
+    use super::{ProgressObserver, Transfer, TransferPhase};
+
+    #[doc = "Renders one indicatif progress bar per transfer"]
+    pub struct IndicatifProgress {
+        bars: MultiProgress,
+        active: Mutex<HashMap<(Uuid, usize), ProgressBar>>,
+        style: ProgressStyle,
+    }
+
+    impl IndicatifProgress {
+        pub fn new(bars: MultiProgress) -> Self {
+            Self {
+                bars,
+                active: Mutex::new(HashMap::new()),
+                style: ProgressStyle::with_template(
+                    "{msg} [{bar:40}] {bytes}/{total_bytes} ({bytes_per_sec}, {eta})",
+                )
+                .unwrap_or_else(|_| ProgressStyle::default_bar())
+                .progress_chars("=> "),
+            }
+        }
+
+        pub fn with_style(mut self, style: ProgressStyle) -> Self {
+            self.style = style;
+            self
+        }
+    }
+
+    impl std::fmt::Debug for IndicatifProgress {
+        fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+            f.debug_struct("IndicatifProgress")
+                .field("bars", &self.bars)
+                .finish_non_exhaustive()
+        }
+    }
+
+    impl Default for IndicatifProgress {
+        fn default() -> Self {
+            Self::new(MultiProgress::new())
+        }
+    }
+
+    impl ProgressObserver for IndicatifProgress {
+        fn update(&self, transfer: &Transfer) {
+            let Ok(mut active) = self.active.lock() else {
+                return;
+            };
+            let key = (transfer.image, transfer.index);
+            if transfer.phase == TransferPhase::Finished {
+                if let Some(bar) = active.remove(&key) {
+                    bar.finish();
+                }
+                return;
+            }
+            let bar = active.entry(key).or_insert_with(|| {
+                let bar = self.bars.add(ProgressBar::new(transfer.total.unwrap_or(0)));
+                bar.set_style(self.style.clone());
+                bar
+            });
+            bar.set_message(format!("{} {}", phase_name(transfer.phase), transfer.image));
+            if let Some(total) = transfer.total {
+                bar.set_length(total);
+            }
+            bar.set_position(transfer.transferred);
+        }
+    }
+
+    fn phase_name(phase: TransferPhase) -> &'static str {
+        match phase {
+            TransferPhase::Downloading => "downloading",
+            TransferPhase::Uploading => "uploading",
+            TransferPhase::Verifying => "verifying",
+            TransferPhase::Finished => "finished",
+        }
+    }
+}
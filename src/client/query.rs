@@ -0,0 +1,328 @@
+use derive_builder::Builder;
+use indexmap::IndexMap;
+use serde::Serialize;
+use uuid::Uuid;
+
+use url::Url;
+
+use crate::manifest::{
+    ImageFileCompression, ImageOs, ImageRequirements, ImageState, ImageType, ImageUsers, Manifest,
+    TagValue, TraitValue,
+};
+
+use crate::ser::wire_name;
+use crate::units::Bytes;
+
+use super::digest::Digests;
+use super::ParamsBuilderError;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StateFilter {
+    //Images in any state; without a state filter IMGAPI only lists active images.
+    All,
+    Is(ImageState),
+}
+
+impl From<ImageState> for StateFilter {
+    fn from(value: ImageState) -> Self {
+        StateFilter::Is(value)
+    }
+}
+
+#[doc = "Server side filters for ListImages"]
+#[derive(Debug, Clone, Default, Builder)]
+#[builder(build_fn(error = "ParamsBuilderError"))]
+pub struct ListImagesQuery {
+    #[builder(setter(into, strip_option), default)]
+    pub name: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub version: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub owner: Option<Uuid>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub os: Option<ImageOs>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub image_type: Option<ImageType>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub state: Option<StateFilter>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub public: Option<bool>,
+
+    //Sent as tag.KEY=VALUE, use the tag() setter to add entries.
+    #[builder(setter(custom), default)]
+    pub tags: IndexMap<String, String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub billing_tag: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub channel: Option<String>,
+
+    //Maximum number of images to return. IMGAPI caps this at 1000.
+    #[builder(setter(into, strip_option), default)]
+    pub limit: Option<u32>,
+
+    //Only return images published at or after this image.
+    #[builder(setter(into, strip_option), default)]
+    pub marker: Option<Uuid>,
+
+    //Include operator-only fields such as files[].stor.
+    #[builder(default)]
+    pub incl_admin_fields: bool,
+}
+
+impl ListImagesQueryBuilder {
+    pub fn tag(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.tags
+            .get_or_insert_with(IndexMap::new)
+            .insert(key.into(), value.into());
+        self
+    }
+}
+
+impl ListImagesQuery {
+    /// The query string parameters for this filter.
+    pub fn to_query(&self) -> Vec<(String, String)> {
+        let mut query = vec![];
+        let mut push = |key: &str, value: Option<String>| {
+            if let Some(value) = value {
+                query.push((key.to_string(), value));
+            }
+        };
+
+        push("name", self.name.clone());
+        push("version", self.version.clone());
+        push("owner", self.owner.map(|o| o.to_string()));
+        push("os", self.os.as_ref().map(wire_name));
+        push("type", self.image_type.as_ref().map(wire_name));
+        push(
+            "state",
+            self.state.as_ref().map(|state| match state {
+                StateFilter::All => "all".to_string(),
+                StateFilter::Is(state) => wire_name(state),
+            }),
+        );
+        push("public", self.public.map(|p| p.to_string()));
+        for (key, value) in &self.tags {
+            push(&format!("tag.{}", key), Some(value.clone()));
+        }
+        push("billing_tag", self.billing_tag.clone());
+        push("channel", self.channel.clone());
+        push("limit", self.limit.map(|l| l.to_string()));
+        push("marker", self.marker.map(|m| m.to_string()));
+        push(
+            "inclAdminFields",
+            self.incl_admin_fields.then(|| "true".to_string()),
+        );
+        query
+    }
+}
+
+#[doc = "Query parameters for AddImageFile"]
+#[derive(Debug, Clone, Builder)]
+#[builder(build_fn(error = "ParamsBuilderError"))]
+pub struct AddImageFileParams {
+    //The compression of the uploaded file.
+    pub compression: ImageFileCompression,
+
+    //SHA-1 of the file, verified by the server after the upload.
+    #[builder(setter(into, strip_option), default)]
+    pub sha1: Option<String>,
+
+    //Size of the file, also sent as Content-Length.
+    #[builder(setter(into, strip_option), default)]
+    pub size: Option<Bytes>,
+
+    //ZFS guid of the dataset snapshot in the file, see ImageFile.dataset_guid.
+    #[builder(setter(into, strip_option), default)]
+    pub dataset_guid: Option<String>,
+
+    //Base64 MD5 of the file, sent as Content-MD5 for the server to check.
+    #[builder(setter(into, strip_option), default)]
+    pub content_md5: Option<String>,
+}
+
+impl AddImageFileParamsBuilder {
+    /// Take sha1, size and content_md5 from digests computed beforehand.
+    pub fn digests(&mut self, digests: &Digests) -> &mut Self {
+        self.sha1(digests.sha1_hex())
+            .size(Bytes(digests.len))
+            .content_md5(digests.content_md5())
+    }
+}
+
+impl AddImageFileParams {
+    pub fn to_query(&self) -> Vec<(String, String)> {
+        let mut query = vec![("compression".to_string(), wire_name(&self.compression))];
+        if let Some(sha1) = &self.sha1 {
+            query.push(("sha1".into(), sha1.clone()));
+        }
+        if let Some(size) = self.size {
+            query.push(("size".into(), size.get().to_string()));
+        }
+        if let Some(dataset_guid) = &self.dataset_guid {
+            query.push(("dataset_guid".into(), dataset_guid.clone()));
+        }
+        query
+    }
+}
+
+#[doc = "The mutable manifest fields accepted by UpdateImage, unset fields are left unchanged"]
+#[derive(Debug, Clone, Default, Serialize, Builder)]
+#[builder(build_fn(error = "ParamsBuilderError"))]
+pub struct UpdateImagePayload {
+    #[builder(setter(into, strip_option), default)]
+    pub name: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub version: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub description: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub homepage: Option<Url>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub eula: Option<Url>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub public: Option<bool>,
+
+    //Replaces the whole ACL.
+    #[builder(setter(into, strip_option), default)]
+    pub acl: Option<Vec<Uuid>>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub requirements: Option<ImageRequirements>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub users: Option<Vec<ImageUsers>>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub billing_tags: Option<Vec<String>>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub traits: Option<IndexMap<String, TraitValue>>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub tags: Option<IndexMap<String, TagValue>>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub generate_password: Option<bool>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub inherited_directories: Option<Vec<String>>,
+}
+
+#[doc = "Query parameters for AdminImportImage"]
+#[derive(Debug, Clone, Default, Builder)]
+#[builder(build_fn(error = "ParamsBuilderError"))]
+pub struct AdminImportOptions {
+    //Import even if the owner account is unknown to this server.
+    #[builder(default)]
+    pub skip_owner_check: bool,
+
+    //IMGAPI to fetch the image file from as part of the import.
+    #[builder(setter(into, strip_option), default)]
+    pub source: Option<Url>,
+}
+
+impl AdminImportOptions {
+    pub fn to_query(&self) -> Vec<(String, String)> {
+        let mut query = vec![];
+        if self.skip_owner_check {
+            query.push(("skip_owner_check".into(), "true".into()));
+        }
+        if let Some(source) = &self.source {
+            query.push(("source".into(), source.to_string()));
+        }
+        query
+    }
+}
+
+#[doc = "A change to the ACL of a private image"]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AclAction {
+    //AddImageAcl: grant these accounts access.
+    Add(Vec<Uuid>),
+    //RemoveImageAcl: revoke access from these accounts.
+    Remove(Vec<Uuid>),
+}
+
+impl AclAction {
+    pub(crate) fn action(&self) -> &'static str {
+        match self {
+            AclAction::Add(_) => "add",
+            AclAction::Remove(_) => "remove",
+        }
+    }
+
+    pub(crate) fn accounts(&self) -> &[Uuid] {
+        match self {
+            AclAction::Add(accounts) | AclAction::Remove(accounts) => accounts,
+        }
+    }
+}
+
+#[doc = "Query parameters for DeleteImage"]
+#[derive(Debug, Clone, Default, Builder)]
+#[builder(build_fn(error = "ParamsBuilderError"))]
+pub struct DeleteImageOptions {
+    //On channel enabled servers, only remove the image from this channel.
+    #[builder(setter(into, strip_option), default)]
+    pub channel: Option<String>,
+
+    //Delete the image even if it is still in channels other than `channel`.
+    #[builder(default)]
+    pub force_all_channels: bool,
+}
+
+impl DeleteImageOptions {
+    pub fn to_query(&self) -> Vec<(String, String)> {
+        let mut query = vec![];
+        if let Some(channel) = &self.channel {
+            query.push(("channel".into(), channel.clone()));
+        }
+        if self.force_all_channels {
+            query.push(("force_all_channels".into(), "true".into()));
+        }
+        query
+    }
+}
+
+//Page size used by the paged listings when the query sets no limit.
+pub(crate) const DEFAULT_PAGE_SIZE: u32 = 1000;
+
+/// Turn a fetched page into the query for the following page. IMGAPI
+/// includes the marker image itself at the start of the next page, so it is
+/// removed from `page`. Returns None once the listing is exhausted.
+pub(crate) fn advance_page(
+    mut query: ListImagesQuery,
+    page: &mut Vec<Manifest>,
+) -> Option<ListImagesQuery> {
+    let full = page.len() >= query.limit.unwrap_or(DEFAULT_PAGE_SIZE) as usize;
+    if query.marker.is_some() && page.first().map(|m| m.uuid) == query.marker {
+        page.remove(0);
+    }
+    match page.last() {
+        Some(last) if full => {
+            query.marker = Some(last.uuid);
+            Some(query)
+        }
+        _ => None,
+    }
+}
+
+/// The query for the first page, always with an explicit limit.
+pub(crate) fn first_page(mut query: ListImagesQuery) -> ListImagesQuery {
+    //A limit of 1 would only ever return the marker again
+    query.limit = Some(query.limit.unwrap_or(DEFAULT_PAGE_SIZE).max(2));
+    query
+}
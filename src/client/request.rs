@@ -0,0 +1,141 @@
+//! Request handling shared by Client and BlockingClient. Each client only
+//! adds the transport call and the wait between attempts.
+
+use reqwest::header::HeaderMap;
+use reqwest::{Method, StatusCode};
+use std::time::Duration;
+use url::Url;
+
+use super::retry::retry_after;
+use super::{AuthProvider, MetadataCache, RetryPolicy};
+use crate::error::Result;
+
+//The parts of a reqwest request, async or blocking, the send loop works on.
+pub(crate) trait Sendable: Sized {
+    fn method(&self) -> &Method;
+    fn url(&self) -> &Url;
+    fn headers_mut(&mut self) -> &mut HeaderMap;
+    fn try_clone(&self) -> Option<Self>;
+}
+
+impl Sendable for reqwest::Request {
+    fn method(&self) -> &Method {
+        self.method()
+    }
+
+    fn url(&self) -> &Url {
+        self.url()
+    }
+
+    fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.headers_mut()
+    }
+
+    fn try_clone(&self) -> Option<Self> {
+        self.try_clone()
+    }
+}
+
+#[cfg(feature = "blocking")]
+impl Sendable for reqwest::blocking::Request {
+    fn method(&self) -> &Method {
+        self.method()
+    }
+
+    fn url(&self) -> &Url {
+        self.url()
+    }
+
+    fn headers_mut(&mut self) -> &mut HeaderMap {
+        self.headers_mut()
+    }
+
+    fn try_clone(&self) -> Option<Self> {
+        self.try_clone()
+    }
+}
+
+pub(crate) const JSON: &str = "application/json";
+
+/// The URL of `path` below the normalized `base` URL, for image calls with
+/// the channel the client targets.
+pub(crate) fn image_url(base: &Url, path: &str, channel: Option<&str>) -> Result<Url> {
+    let mut url = base.join(path)?;
+    if let Some(channel) = channel {
+        url.query_pairs_mut().append_pair("channel", channel);
+    }
+    Ok(url)
+}
+
+/// Add credentials to attempt number `attempt` of `req`, returning the copy
+/// to send again if the policy allows another attempt. Streaming bodies
+/// cannot be cloned and are only sent once. Without `auth` the request goes
+/// out anonymously.
+pub(crate) fn prepare_attempt<R: Sendable>(
+    retry: &RetryPolicy,
+    auth: Option<&dyn AuthProvider>,
+    req: &mut R,
+    attempt: u32,
+) -> Result<Option<R>> {
+    let next = retry
+        .allows(req.method(), attempt)
+        .then(|| req.try_clone())
+        .flatten();
+    if let Some(auth) = auth {
+        let (method, url) = (req.method().clone(), req.url().clone());
+        auth.authorize(&method, &url, req.headers_mut())?;
+    }
+    Ok(next)
+}
+
+/// How long to wait before repeating an attempt that ended in `outcome`,
+/// None if it is final.
+pub(crate) fn retry_delay(
+    retry: &RetryPolicy,
+    outcome: std::result::Result<(StatusCode, &HeaderMap), &reqwest::Error>,
+    attempt: u32,
+) -> Option<Duration> {
+    match outcome {
+        Ok((status, headers)) => retry.retry_delay(status, headers, attempt),
+        Err(err) => retry.retry_error(err).then(|| retry.delay(attempt)),
+    }
+}
+
+/// The status and Retry-After of a response to turn into an error with
+/// [`super::api_error`] once its body is read, None for responses to pass
+/// on. 304 only answers the conditional requests of get_json.
+pub(crate) fn failure(status: StatusCode, headers: &HeaderMap) -> Option<(u16, Option<Duration>)> {
+    if status.is_success() || status == StatusCode::NOT_MODIFIED {
+        return None;
+    }
+    Some((status.as_u16(), retry_after(headers)))
+}
+
+/// Make `headers` conditional on the cached copy of `url`, returning the
+/// cache key.
+pub(crate) fn add_cache_conditions(
+    cache: &MetadataCache,
+    url: &Url,
+    headers: &mut HeaderMap,
+) -> String {
+    let key = url.to_string();
+    cache.add_conditions(&key, headers);
+    key
+}
+
+/// The body of a cached GET: the cached one if the server answered 304,
+/// otherwise the `fetched` one, which is cached for next time.
+pub(crate) fn cached_body(
+    cache: &MetadataCache,
+    key: &str,
+    headers: &HeaderMap,
+    fetched: Option<Vec<u8>>,
+) -> Result<Vec<u8>> {
+    match fetched {
+        Some(body) => {
+            cache.store(key, headers, &body);
+            Ok(body)
+        }
+        None => cache.body(key).ok_or_else(super::not_cached),
+    }
+}
@@ -0,0 +1,139 @@
+use derive_builder::Builder;
+use reqwest::header::{HeaderMap, RETRY_AFTER};
+use reqwest::{Method, StatusCode};
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::{Duration, SystemTime};
+
+use super::ParamsBuilderError;
+
+#[doc = "When and how often a client repeats failed requests"]
+#[derive(Debug, Clone, Builder)]
+#[builder(build_fn(error = "ParamsBuilderError"))]
+pub struct RetryPolicy {
+    //Attempts including the first one, 1 disables retries.
+    #[builder(default = "3")]
+    pub max_attempts: u32,
+
+    //Delay before the first retry, doubled for every further one.
+    #[builder(default = "Duration::from_millis(200)")]
+    pub base_delay: Duration,
+
+    #[builder(default = "Duration::from_secs(10)")]
+    pub max_delay: Duration,
+
+    //Pick a random delay up to the backoff ("full jitter") so that many
+    //clients failing at once do not retry in lockstep.
+    #[builder(default = "true")]
+    pub jitter: bool,
+
+    //Also retry POST requests, which IMGAPI does not treat as idempotent.
+    #[builder(default = "false")]
+    pub retry_non_idempotent: bool,
+
+    //Wait as long as a 429 or 503 response's Retry-After asks instead of
+    //backing off, up to max_retry_after. Longer waits fail with RateLimited.
+    #[builder(default = "true")]
+    pub respect_retry_after: bool,
+
+    #[builder(default = "Duration::from_secs(60)")]
+    pub max_retry_after: Duration,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self {
+            max_attempts: 3,
+            base_delay: Duration::from_millis(200),
+            max_delay: Duration::from_secs(10),
+            jitter: true,
+            retry_non_idempotent: false,
+            respect_retry_after: true,
+            max_retry_after: Duration::from_secs(60),
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// Send every request once, the client default.
+    pub fn none() -> Self {
+        Self {
+            max_attempts: 1,
+            ..Self::default()
+        }
+    }
+
+    /// How long to wait after failed attempt number `attempt`, starting at 1.
+    pub fn delay(&self, attempt: u32) -> Duration {
+        let backoff = self
+            .base_delay
+            .saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)))
+            .min(self.max_delay);
+        if !self.jitter {
+            return backoff;
+        }
+        let random = RandomState::new().build_hasher().finish();
+        backoff.mul_f64((random >> 11) as f64 / (1u64 << 53) as f64)
+    }
+
+    /// Whether a request with `method` may be sent again after `attempt`
+    /// attempts.
+    pub fn allows(&self, method: &Method, attempt: u32) -> bool {
+        attempt < self.max_attempts && (self.retry_non_idempotent || is_idempotent(method))
+    }
+
+    /// How long to wait before retrying a request answered with `status`
+    /// after `attempt` attempts, None if it should not be retried.
+    pub fn retry_delay(
+        &self,
+        status: StatusCode,
+        headers: &HeaderMap,
+        attempt: u32,
+    ) -> Option<Duration> {
+        if self.respect_retry_after && is_rate_limit(status) {
+            if let Some(after) = retry_after(headers) {
+                return (after <= self.max_retry_after).then_some(after);
+            }
+        }
+        self.retry_status(status).then(|| self.delay(attempt))
+    }
+
+    /// Whether `status` is worth retrying: server errors, overload and rate
+    /// limits, not client errors.
+    pub fn retry_status(&self, status: StatusCode) -> bool {
+        matches!(
+            status,
+            StatusCode::TOO_MANY_REQUESTS
+                | StatusCode::INTERNAL_SERVER_ERROR
+                | StatusCode::BAD_GATEWAY
+                | StatusCode::SERVICE_UNAVAILABLE
+                | StatusCode::GATEWAY_TIMEOUT
+        )
+    }
+
+    /// Whether `err` is a transient network failure.
+    pub fn retry_error(&self, err: &reqwest::Error) -> bool {
+        err.is_connect() || err.is_timeout()
+    }
+}
+
+fn is_idempotent(method: &Method) -> bool {
+    matches!(
+        *method,
+        Method::GET | Method::HEAD | Method::PUT | Method::DELETE | Method::OPTIONS
+    )
+}
+
+pub(crate) fn is_rate_limit(status: StatusCode) -> bool {
+    status == StatusCode::TOO_MANY_REQUESTS || status == StatusCode::SERVICE_UNAVAILABLE
+}
+
+/// The Retry-After header, given either in seconds or as an HTTP date.
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let at = httpdate::parse_http_date(value).ok()?;
+    Some(at.duration_since(SystemTime::now()).unwrap_or_default())
+}
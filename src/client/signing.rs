@@ -0,0 +1,173 @@
+//! Joyent HTTP Signature authentication, as used by IMGAPI and the other
+//! Triton APIs: the Date header is signed with the caller's SSH key and sent
+//! as `Authorization: Signature keyId=...`.
+
+use base64::Engine;
+use md5::{Digest, Md5};
+use p256::ecdsa::signature::Signer as _;
+use reqwest::header::{HeaderMap, HeaderValue, AUTHORIZATION, DATE};
+use ssh_key::{Algorithm, EcdsaCurve, PrivateKey, PublicKey};
+use std::path::Path;
+use std::time::SystemTime;
+
+use crate::error::{ImgapiError, Result};
+
+#[cfg(unix)]
+mod agent;
+#[cfg(unix)]
+pub use agent::{identities, AgentSigner};
+
+/// Signs requests on behalf of an account.
+pub trait Signer: Send + Sync + std::fmt::Debug {
+    /// The keyId parameter of the Authorization header, usually
+    /// `/<account>/keys/<md5 fingerprint>`.
+    fn key_id(&self) -> &str;
+
+    /// The http-signature algorithm name, e.g. `rsa-sha256`.
+    fn algorithm(&self) -> &str;
+
+    /// The raw signature of `data`.
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>>;
+}
+
+#[doc = "A Signer for an unencrypted RSA, ECDSA (P-256/P-384) or Ed25519 OpenSSH private key"]
+pub struct KeySigner {
+    key: PrivateKey,
+    key_id: String,
+    algorithm: &'static str,
+}
+
+impl KeySigner {
+    pub fn new(key: PrivateKey, account: &str) -> Result<Self> {
+        if key.is_encrypted() {
+            return Err(ImgapiError::Signing("the private key is encrypted".into()));
+        }
+        let algorithm = http_signature_algorithm(&key.algorithm())?;
+        Ok(Self {
+            key_id: format!("/{}/keys/{}", account, md5_fingerprint(key.public_key())?),
+            key,
+            algorithm,
+        })
+    }
+
+    pub fn from_openssh(pem: impl AsRef<[u8]>, account: &str) -> Result<Self> {
+        Self::new(PrivateKey::from_openssh(pem)?, account)
+    }
+
+    pub fn from_path(path: impl AsRef<Path>, account: &str) -> Result<Self> {
+        Self::from_openssh(std::fs::read(path)?, account)
+    }
+
+    /// Use `key_id` instead of the default `/<account>/keys/<fingerprint>`.
+    pub fn with_key_id(mut self, key_id: impl Into<String>) -> Self {
+        self.key_id = key_id.into();
+        self
+    }
+}
+
+//Never print the key itself
+impl std::fmt::Debug for KeySigner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("KeySigner")
+            .field("key_id", &self.key_id)
+            .field("algorithm", &self.algorithm)
+            .finish()
+    }
+}
+
+impl Signer for KeySigner {
+    fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    fn algorithm(&self) -> &str {
+        self.algorithm
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let signature: ssh_key::Signature = self
+            .key
+            .try_sign(data)
+            .map_err(|e| ImgapiError::Signing(e.to_string()))?;
+        raw_signature(&signature)
+    }
+}
+
+/// The colon separated MD5 fingerprint Triton uses in key ids, e.g.
+/// `c2:43:f6:ab:...`.
+pub fn md5_fingerprint(public_key: &PublicKey) -> Result<String> {
+    let digest = Md5::digest(public_key.to_bytes()?);
+    Ok(digest
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<Vec<_>>()
+        .join(":"))
+}
+
+//ssh-key signs RSA keys with SHA-512 and ECDSA keys with the curve's hash.
+pub(crate) fn http_signature_algorithm(algorithm: &Algorithm) -> Result<&'static str> {
+    match algorithm {
+        Algorithm::Rsa { .. } => Ok("rsa-sha512"),
+        Algorithm::Ecdsa {
+            curve: EcdsaCurve::NistP256,
+        } => Ok("ecdsa-sha256"),
+        Algorithm::Ecdsa {
+            curve: EcdsaCurve::NistP384,
+        } => Ok("ecdsa-sha384"),
+        Algorithm::Ed25519 => Ok("ed25519-sha512"),
+        other => Err(ImgapiError::Signing(format!(
+            "{} keys are not supported",
+            other
+        ))),
+    }
+}
+
+/// Turn an SSH signature blob into what http-signature expects: PKCS#1 and
+/// Ed25519 signatures as is, ECDSA signatures DER encoded.
+pub(crate) fn raw_signature(signature: &ssh_key::Signature) -> Result<Vec<u8>> {
+    let der = |e: ssh_key::Error| ImgapiError::Signing(e.to_string());
+    match signature.algorithm() {
+        Algorithm::Ecdsa {
+            curve: EcdsaCurve::NistP256,
+        } => Ok(p256::ecdsa::Signature::try_from(signature)
+            .map_err(der)?
+            .to_der()
+            .as_bytes()
+            .to_vec()),
+        Algorithm::Ecdsa {
+            curve: EcdsaCurve::NistP384,
+        } => Ok(p384::ecdsa::Signature::try_from(signature)
+            .map_err(der)?
+            .to_der()
+            .as_bytes()
+            .to_vec()),
+        _ => Ok(signature.as_bytes().to_vec()),
+    }
+}
+
+/// Set the Date header, unless present, and the Authorization header
+/// signing it.
+pub(crate) fn sign_headers(signer: &dyn Signer, headers: &mut HeaderMap) -> Result<()> {
+    if !headers.contains_key(DATE) {
+        let now = httpdate::fmt_http_date(SystemTime::now());
+        headers.insert(DATE, header_value(&now)?);
+    }
+    let date = headers
+        .get(DATE)
+        .and_then(|date| date.to_str().ok())
+        .unwrap_or_default();
+
+    let signature = signer.sign(format!("date: {}", date).as_bytes())?;
+    let authorization = format!(
+        "Signature keyId=\"{}\",algorithm=\"{}\",headers=\"date\",signature=\"{}\"",
+        signer.key_id(),
+        signer.algorithm(),
+        base64::engine::general_purpose::STANDARD.encode(signature)
+    );
+    headers.insert(AUTHORIZATION, header_value(&authorization)?);
+    Ok(())
+}
+
+fn header_value(value: &str) -> Result<HeaderValue> {
+    HeaderValue::from_str(value).map_err(|e| ImgapiError::Signing(e.to_string()))
+}
@@ -0,0 +1,180 @@
+//! A Signer backed by ssh-agent, so the private key never enters the process.
+//! Speaks the agent protocol (draft-miller-ssh-agent) over SSH_AUTH_SOCK.
+
+use ssh_key::{Algorithm, HashAlg, PublicKey};
+use std::io::{Read, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use super::{http_signature_algorithm, md5_fingerprint, raw_signature, Signer};
+use crate::error::{ImgapiError, Result};
+
+const AGENT_FAILURE: u8 = 5;
+const AGENTC_REQUEST_IDENTITIES: u8 = 11;
+const AGENT_IDENTITIES_ANSWER: u8 = 12;
+const AGENTC_SIGN_REQUEST: u8 = 13;
+const AGENT_SIGN_RESPONSE: u8 = 14;
+//Ask for rsa-sha2-512 instead of the SHA-1 based ssh-rsa, matching KeySigner.
+const AGENT_RSA_SHA2_512: u32 = 4;
+
+#[doc = "A Signer using a key held by ssh-agent"]
+#[derive(Debug, Clone)]
+pub struct AgentSigner {
+    socket: PathBuf,
+    key: PublicKey,
+    key_id: String,
+    algorithm: &'static str,
+}
+
+impl AgentSigner {
+    /// Sign with the first key of the agent at SSH_AUTH_SOCK.
+    pub fn new(account: &str) -> Result<Self> {
+        let socket = auth_sock()?;
+        let key = identities(&socket)?
+            .into_iter()
+            .find(|key| http_signature_algorithm(&key.algorithm()).is_ok())
+            .ok_or_else(|| ImgapiError::Signing("ssh-agent holds no usable keys".into()))?;
+        Self::with_key(socket, key, account)
+    }
+
+    /// Sign with the agent key whose fingerprint is `fingerprint`, either
+    /// as MD5 (`c2:43:f6:...`, optionally prefixed with `MD5:`) or as
+    /// `SHA256:...`.
+    pub fn with_fingerprint(account: &str, fingerprint: &str) -> Result<Self> {
+        let socket = auth_sock()?;
+        let md5 = fingerprint.strip_prefix("MD5:").unwrap_or(fingerprint);
+        for key in identities(&socket)? {
+            if md5_fingerprint(&key)? == md5
+                || key.fingerprint(HashAlg::Sha256).to_string() == fingerprint
+            {
+                return Self::with_key(socket, key, account);
+            }
+        }
+        Err(ImgapiError::Signing(format!(
+            "ssh-agent has no key with fingerprint {}",
+            fingerprint
+        )))
+    }
+
+    /// Sign with `key` through the agent listening on `socket`.
+    pub fn with_key(socket: impl Into<PathBuf>, key: PublicKey, account: &str) -> Result<Self> {
+        Ok(Self {
+            socket: socket.into(),
+            algorithm: http_signature_algorithm(&key.algorithm())?,
+            key_id: format!("/{}/keys/{}", account, md5_fingerprint(&key)?),
+            key,
+        })
+    }
+
+    /// Use `key_id` instead of the default `/<account>/keys/<fingerprint>`.
+    pub fn with_key_id(mut self, key_id: impl Into<String>) -> Self {
+        self.key_id = key_id.into();
+        self
+    }
+}
+
+impl Signer for AgentSigner {
+    fn key_id(&self) -> &str {
+        &self.key_id
+    }
+
+    fn algorithm(&self) -> &str {
+        self.algorithm
+    }
+
+    fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let mut request = vec![AGENTC_SIGN_REQUEST];
+        put_string(&mut request, &self.key.to_bytes()?);
+        put_string(&mut request, data);
+        let flags = match self.key.algorithm() {
+            Algorithm::Rsa { .. } => AGENT_RSA_SHA2_512,
+            _ => 0,
+        };
+        request.extend_from_slice(&flags.to_be_bytes());
+
+        let response = call(&self.socket, &request, AGENT_SIGN_RESPONSE)?;
+        let mut blob = Reader(get_string(&mut Reader(&response))?);
+        let algorithm = std::str::from_utf8(get_string(&mut blob)?)
+            .ok()
+            .and_then(|name| Algorithm::new(name).ok())
+            .ok_or_else(|| protocol_error("unknown signature algorithm"))?;
+        let signature = ssh_key::Signature::new(algorithm, get_string(&mut blob)?)?;
+        raw_signature(&signature)
+    }
+}
+
+/// The keys held by the agent listening on `socket`.
+pub fn identities(socket: &Path) -> Result<Vec<PublicKey>> {
+    let response = call(
+        socket,
+        &[AGENTC_REQUEST_IDENTITIES],
+        AGENT_IDENTITIES_ANSWER,
+    )?;
+    let mut reader = Reader(&response);
+    let count = get_u32(&mut reader)?;
+    let mut keys = vec![];
+    for _ in 0..count {
+        let blob = get_string(&mut reader)?;
+        let _comment = get_string(&mut reader)?;
+        //Skip key types ssh-key does not know, e.g. certificates
+        if let Ok(key) = PublicKey::from_bytes(blob) {
+            keys.push(key);
+        }
+    }
+    Ok(keys)
+}
+
+fn auth_sock() -> Result<PathBuf> {
+    std::env::var_os("SSH_AUTH_SOCK")
+        .map(PathBuf::from)
+        .ok_or_else(|| ImgapiError::Signing("SSH_AUTH_SOCK is not set".into()))
+}
+
+//Send one message and read the reply, which must be of type `expected`.
+fn call(socket: &Path, message: &[u8], expected: u8) -> Result<Vec<u8>> {
+    let mut stream = UnixStream::connect(socket)?;
+    stream.write_all(&(message.len() as u32).to_be_bytes())?;
+    stream.write_all(message)?;
+
+    let mut len = [0u8; 4];
+    stream.read_exact(&mut len)?;
+    let mut reply = vec![0u8; u32::from_be_bytes(len) as usize];
+    stream.read_exact(&mut reply)?;
+    match reply.split_first() {
+        Some((kind, body)) if *kind == expected => Ok(body.to_vec()),
+        Some((&AGENT_FAILURE, _)) => {
+            Err(ImgapiError::Signing("ssh-agent refused the request".into()))
+        }
+        _ => Err(protocol_error("unexpected reply")),
+    }
+}
+
+fn protocol_error(message: &str) -> ImgapiError {
+    ImgapiError::Signing(format!("ssh-agent protocol error: {}", message))
+}
+
+fn put_string(buf: &mut Vec<u8>, data: &[u8]) {
+    buf.extend_from_slice(&(data.len() as u32).to_be_bytes());
+    buf.extend_from_slice(data);
+}
+
+struct Reader<'a>(&'a [u8]);
+
+fn get_u32(reader: &mut Reader) -> Result<u32> {
+    let (head, rest) = reader
+        .0
+        .split_first_chunk::<4>()
+        .ok_or_else(|| protocol_error("truncated message"))?;
+    reader.0 = rest;
+    Ok(u32::from_be_bytes(*head))
+}
+
+fn get_string<'a>(reader: &mut Reader<'a>) -> Result<&'a [u8]> {
+    let len = get_u32(reader)? as usize;
+    if reader.0.len() < len {
+        return Err(protocol_error("truncated message"));
+    }
+    let (data, rest) = reader.0.split_at(len);
+    reader.0 = rest;
+    Ok(data)
+}
@@ -0,0 +1,76 @@
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+#[doc = "A token bucket limiting the bandwidth of image file transfers"]
+#[derive(Debug, Clone)]
+pub struct Throttle {
+    //Shared by clones, so concurrent transfers share the limit.
+    bucket: Arc<Mutex<Bucket>>,
+}
+
+#[derive(Debug)]
+struct Bucket {
+    //Bytes per second.
+    rate: f64,
+    //Bytes that may be sent at once after an idle period.
+    burst: f64,
+    //Negative while transfers are ahead of the rate.
+    tokens: f64,
+    refilled: Instant,
+}
+
+impl Throttle {
+    /// Limit transfers to `bytes_per_sec`, with a burst of one second worth
+    /// of data.
+    pub fn new(bytes_per_sec: u64) -> Self {
+        let rate = bytes_per_sec.max(1) as f64;
+        Self {
+            bucket: Arc::new(Mutex::new(Bucket {
+                rate,
+                burst: rate,
+                tokens: rate,
+                refilled: Instant::now(),
+            })),
+        }
+    }
+
+    pub fn burst(self, bytes: u64) -> Self {
+        if let Ok(mut bucket) = self.bucket.lock() {
+            bucket.burst = bytes as f64;
+            bucket.tokens = bucket.tokens.min(bucket.burst);
+        }
+        self
+    }
+
+    /// Account for `bytes` being transferred, waiting until the rate allows it.
+    pub async fn acquire(&self, bytes: u64) {
+        let wait = self.reserve(bytes);
+        if !wait.is_zero() {
+            tokio::time::sleep(wait).await;
+        }
+    }
+
+    /// Like acquire, for the blocking client.
+    pub fn acquire_blocking(&self, bytes: u64) {
+        let wait = self.reserve(bytes);
+        if !wait.is_zero() {
+            std::thread::sleep(wait);
+        }
+    }
+
+    //Take `bytes` from the bucket, returning how long until they are covered.
+    fn reserve(&self, bytes: u64) -> Duration {
+        let Ok(mut bucket) = self.bucket.lock() else {
+            return Duration::ZERO;
+        };
+        let now = Instant::now();
+        let elapsed = now.duration_since(bucket.refilled).as_secs_f64();
+        bucket.tokens = (bucket.tokens + elapsed * bucket.rate).min(bucket.burst);
+        bucket.refilled = now;
+        bucket.tokens -= bytes as f64;
+        if bucket.tokens >= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(-bucket.tokens / bucket.rate)
+    }
+}
@@ -0,0 +1,132 @@
+//! Detection of the actual encoding of image files, to catch files whose
+//! content disagrees with the declared `ImageFile.compression` before they
+//! are published.
+
+use flate2::read::GzDecoder;
+use miette::Diagnostic;
+use std::io::Read;
+use strum::Display as StrumDisplay;
+use thiserror::Error;
+
+use crate::manifest::ImageFileCompression;
+
+//How much of a stream is inspected. Plenty for a gzip header plus the
+//first deflate block needed to peek at the inner stream.
+pub(crate) const SNIFF_LEN: usize = 64 * 1024;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, StrumDisplay)]
+#[strum(serialize_all = "kebab-case")]
+pub enum Encoding {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+    //No known compression magic.
+    Plain,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Detected {
+    pub outer: Encoding,
+    //Encoding of the decompressed stream. Only inspected for gzip.
+    pub inner: Option<Encoding>,
+}
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum CompressionMismatch {
+    #[error("file is declared as {declared} compressed but is {actual}")]
+    #[diagnostic(
+        code(imgapi::compression::mismatch),
+        help("set ImageFile.compression to match the file or recompress it")
+    )]
+    Mismatch {
+        declared: ImageFileCompression,
+        actual: Encoding,
+    },
+
+    #[error("file is compressed twice ({outer} of {inner})")]
+    #[diagnostic(
+        code(imgapi::compression::double),
+        help("decompress the file once before publishing it")
+    )]
+    DoubleCompressed { outer: Encoding, inner: Encoding },
+
+    #[error("{0} compression is not supported by IMGAPI")]
+    #[diagnostic(code(imgapi::compression::unsupported))]
+    Unsupported(Encoding),
+
+    #[error(transparent)]
+    #[diagnostic(code(imgapi::io))]
+    Io(#[from] std::io::Error),
+}
+
+/// Encoding indicated by the magic bytes at the start of `data`.
+pub fn sniff(data: &[u8]) -> Encoding {
+    match data {
+        [0x1f, 0x8b, ..] => Encoding::Gzip,
+        [b'B', b'Z', b'h', ..] => Encoding::Bzip2,
+        [0xfd, b'7', b'z', b'X', b'Z', 0x00, ..] => Encoding::Xz,
+        [0x28, 0xb5, 0x2f, 0xfd, ..] => Encoding::Zstd,
+        _ => Encoding::Plain,
+    }
+}
+
+/// Inspect the start of `reader` for its outer and, for gzip, inner encoding.
+pub fn detect<R: Read>(reader: R) -> std::io::Result<Detected> {
+    let mut head = Vec::with_capacity(SNIFF_LEN);
+    reader.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+
+    let outer = sniff(&head);
+    let inner = match outer {
+        Encoding::Gzip => {
+            let mut inner_head = [0u8; 6];
+            let mut decoder = GzDecoder::new(head.as_slice());
+            let mut read = 0;
+            //A truncated deflate stream errors eventually, what was decoded so far is enough
+            while read < inner_head.len() {
+                match decoder.read(&mut inner_head[read..]) {
+                    Ok(0) | Err(_) => break,
+                    Ok(n) => read += n,
+                }
+            }
+            Some(sniff(&inner_head[..read]))
+        }
+        _ => None,
+    };
+
+    Ok(Detected { outer, inner })
+}
+
+/// Check that the contents of `reader` match the `declared` compression.
+pub fn check<R: Read>(
+    declared: &ImageFileCompression,
+    reader: R,
+) -> Result<Detected, CompressionMismatch> {
+    let detected = detect(reader)?;
+
+    let expected = match declared {
+        ImageFileCompression::Gzip => Encoding::Gzip,
+        ImageFileCompression::Bzip2 => Encoding::Bzip2,
+        ImageFileCompression::None => Encoding::Plain,
+    };
+    if detected.outer != expected {
+        return Err(match detected.outer {
+            Encoding::Xz | Encoding::Zstd => CompressionMismatch::Unsupported(detected.outer),
+            actual => CompressionMismatch::Mismatch {
+                declared: declared.clone(),
+                actual,
+            },
+        });
+    }
+
+    if let Some(inner) = detected.inner {
+        if inner != Encoding::Plain {
+            return Err(CompressionMismatch::DoubleCompressed {
+                outer: detected.outer,
+                inner,
+            });
+        }
+    }
+
+    Ok(detected)
+}
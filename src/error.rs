@@ -0,0 +1,249 @@
+use miette::Diagnostic;
+use serde::Deserialize;
+use std::fmt::Display;
+use strum::{EnumString, IntoStaticStr};
+use thiserror::Error;
+
+use crate::compression::CompressionMismatch;
+use crate::manifest::{InvalidManifest, ManifestBuilderError, MigrationError};
+
+pub type Result<T, E = ImgapiError> = std::result::Result<T, E>;
+
+#[doc = "Crate wide error type, one variant per error category"]
+#[derive(Debug, Error, Diagnostic)]
+#[non_exhaustive]
+pub enum ImgapiError {
+    #[error("failed to parse manifest")]
+    #[diagnostic(
+        code(imgapi::parse),
+        help("check that the input is a JSON IMGAPI manifest; old manifests can be upgraded with manifest::migrate")
+    )]
+    Parse(#[from] serde_json::Error),
+
+    #[error("invalid manifest")]
+    #[diagnostic(code(imgapi::validation))]
+    Validation(#[from] ManifestBuilderError),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    InvalidManifest(#[from] InvalidManifest),
+
+    #[error(transparent)]
+    #[diagnostic(transparent)]
+    Compression(#[from] CompressionMismatch),
+
+    #[error("failed to migrate manifest")]
+    #[diagnostic(code(imgapi::migration))]
+    Migration(#[from] MigrationError),
+
+    #[error(transparent)]
+    #[diagnostic(code(imgapi::io))]
+    Io(#[from] std::io::Error),
+
+    #[error("invalid URL")]
+    #[diagnostic(code(imgapi::url))]
+    InvalidUrl(#[from] url::ParseError),
+
+    #[cfg(feature = "client")]
+    #[error("invalid client parameters")]
+    #[diagnostic(code(imgapi::client::params))]
+    InvalidParams(#[from] crate::client::ParamsBuilderError),
+
+    #[cfg(feature = "client")]
+    #[error("HTTP request failed")]
+    #[diagnostic(code(imgapi::http), help("check that the IMGAPI server is reachable"))]
+    Http(#[from] reqwest::Error),
+
+    #[cfg(feature = "client")]
+    #[error("invalid HTTP header {0:?}")]
+    #[diagnostic(code(imgapi::http::header))]
+    InvalidHeader(String),
+
+    #[cfg(feature = "client")]
+    #[error("{0} is not supported by this image source")]
+    #[diagnostic(code(imgapi::source::unsupported))]
+    Unsupported(&'static str),
+
+    #[cfg(feature = "signing")]
+    #[error("invalid SSH key")]
+    #[diagnostic(
+        code(imgapi::signing::key),
+        help("use an unencrypted OpenSSH private key of type RSA, ECDSA (P-256/P-384) or Ed25519")
+    )]
+    Key(#[from] ssh_key::Error),
+
+    #[cfg(feature = "signing")]
+    #[error("failed to sign request: {0}")]
+    #[diagnostic(code(imgapi::signing))]
+    Signing(String),
+
+    #[error("no image matches {0}")]
+    #[diagnostic(code(imgapi::id::not_found))]
+    ImageNotFound(crate::id::ImageId),
+
+    #[error("{id} matches {} images", candidates.len())]
+    #[diagnostic(
+        code(imgapi::id::ambiguous),
+        help("use a longer UUID prefix or the full UUID")
+    )]
+    AmbiguousImageId {
+        id: crate::id::ImageId,
+        candidates: Vec<uuid::Uuid>,
+    },
+
+    #[error("image {uuid} has no file with index {index}")]
+    #[diagnostic(code(imgapi::missing_file))]
+    MissingFile { uuid: uuid::Uuid, index: usize },
+
+    #[error("checksum mismatch: expected sha1 {expected}, got {actual}")]
+    #[diagnostic(
+        code(imgapi::checksum_mismatch),
+        help("the file was corrupted in transit or the manifest is wrong; retry the download")
+    )]
+    ChecksumMismatch { expected: String, actual: String },
+
+    #[cfg(feature = "store")]
+    #[error("invalid SHA-1 {0:?}")]
+    #[diagnostic(code(imgapi::store::sha1), help("expected 40 hexadecimal digits"))]
+    InvalidSha1(String),
+
+    #[cfg(feature = "sqlite")]
+    #[error("store index query failed")]
+    #[diagnostic(
+        code(imgapi::store::index),
+        help("the index can be removed, it is rebuilt from the stored manifests")
+    )]
+    Index(#[from] rusqlite::Error),
+
+    #[cfg(feature = "sqlite")]
+    #[error("invalid store index query")]
+    #[diagnostic(code(imgapi::store::query))]
+    InvalidQuery(#[from] crate::store::IndexQueryBuilderError),
+
+    #[error("icon is {size} bytes, IMGAPI accepts at most {max}")]
+    #[diagnostic(code(imgapi::icon::too_large))]
+    IconTooLarge { size: usize, max: usize },
+
+    #[error("icon is not a PNG, JPEG or GIF image")]
+    #[diagnostic(code(imgapi::icon::unsupported))]
+    UnsupportedIcon,
+
+    #[error("IMGAPI rejected the request: {message}")]
+    #[diagnostic(code(imgapi::api::validation_failed))]
+    ValidationFailed {
+        message: String,
+        #[related]
+        errors: Vec<FieldError>,
+    },
+
+    #[error("image has dependent images: {message}")]
+    #[diagnostic(
+        code(imgapi::api::dependent_images),
+        help("delete the incremental images that use this image as their origin first")
+    )]
+    HasDependentImages { message: String },
+
+    #[error("IMGAPI rate limited the request ({status}): {message}")]
+    #[diagnostic(
+        code(imgapi::api::rate_limited),
+        help(
+            "wait for retry_after before sending more requests, or give the client a RetryPolicy"
+        )
+    )]
+    RateLimited {
+        status: u16,
+        //From the Retry-After header.
+        retry_after: Option<std::time::Duration>,
+        message: String,
+    },
+
+    #[error("IMGAPI returned {status} {code}: {message}")]
+    #[diagnostic(code(imgapi::api))]
+    Api {
+        status: u16,
+        code: ImgapiErrorCode,
+        message: String,
+    },
+}
+
+impl ImgapiError {
+    /// The IMGAPI error code of an error the server returned.
+    pub fn api_code(&self) -> Option<ImgapiErrorCode> {
+        match self {
+            ImgapiError::Api { code, .. } => Some(code.clone()),
+            ImgapiError::ValidationFailed { .. } => Some(ImgapiErrorCode::ValidationFailed),
+            ImgapiError::HasDependentImages { .. } => {
+                Some(ImgapiErrorCode::ImageHasDependentImages)
+            }
+            _ => None,
+        }
+    }
+}
+
+#[doc = "The code field of IMGAPI error responses"]
+#[derive(Debug, Clone, PartialEq, Eq, Hash, EnumString, IntoStaticStr)]
+#[non_exhaustive]
+pub enum ImgapiErrorCode {
+    AccountDoesNotExist,
+    BadRequest,
+    ImageAlreadyActivated,
+    ImageFilesImmutable,
+    ImageHasDependentImages,
+    ImageUuidAlreadyExists,
+    InsufficientServerVersion,
+    InternalError,
+    InvalidHeader,
+    InvalidParameter,
+    NoActivationNoFile,
+    NotAuthorized,
+    NotAvailable,
+    NotImageOwner,
+    NotMantaPathOwner,
+    OperatorOnly,
+    OriginDoesNotExist,
+    OwnerDoesNotExist,
+    RemoteSourceError,
+    ResourceNotFound,
+    ServiceUnavailable,
+    StorageIsDown,
+    StorageUnsupported,
+    Unauthorized,
+    Upload,
+    ValidationFailed,
+    //Codes this crate does not know, empty if the body had none.
+    #[strum(default)]
+    Other(String),
+}
+
+impl ImgapiErrorCode {
+    pub fn from_code(code: &str) -> Self {
+        //Infallible thanks to the default variant
+        code.parse()
+            .unwrap_or_else(|_| ImgapiErrorCode::Other(code.to_string()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        match self {
+            ImgapiErrorCode::Other(code) => code,
+            known => known.into(),
+        }
+    }
+}
+
+impl Display for ImgapiErrorCode {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[doc = "A single field problem of an IMGAPI ValidationFailed error"]
+#[derive(Debug, Clone, Error, Diagnostic, Deserialize, PartialEq, Eq)]
+#[error("{field}: {message}")]
+#[diagnostic(code(imgapi::api::field))]
+pub struct FieldError {
+    pub field: String,
+    #[serde(default)]
+    pub code: String,
+    #[serde(default)]
+    pub message: String,
+}
@@ -0,0 +1,101 @@
+use miette::Diagnostic;
+use std::fmt::Display;
+use std::str::FromStr;
+use thiserror::Error;
+use uuid::Uuid;
+
+use crate::error::{ImgapiError, Result};
+use crate::manifest::Manifest;
+
+#[doc = "A user supplied reference to an image: full UUID, UUID prefix or name@version"]
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub enum ImageId {
+    Uuid(Uuid),
+    //Lowercased leading part of a UUID, as accepted by imgadm.
+    Prefix(String),
+    NameVersion { name: String, version: String },
+}
+
+#[derive(Debug, Clone, Error, Diagnostic, PartialEq, Eq)]
+#[error("invalid image identifier {0:?}")]
+#[diagnostic(
+    code(imgapi::id::invalid),
+    help("use a UUID, a UUID prefix such as 39b9 or name@version")
+)]
+pub struct ParseImageIdError(pub String);
+
+impl ImageId {
+    /// The image among `manifests` this identifier refers to. Names and
+    /// versions are not unique, of several matches the most recently
+    /// published image is picked. A prefix must match a single image.
+    pub fn select(&self, manifests: impl IntoIterator<Item = Manifest>) -> Result<Manifest> {
+        let mut matches: Vec<Manifest> =
+            manifests.into_iter().filter(|m| self.matches(m)).collect();
+        if matches!(self, ImageId::Prefix(_)) && matches.len() > 1 {
+            return Err(ImgapiError::AmbiguousImageId {
+                id: self.clone(),
+                candidates: matches.iter().map(|m| m.uuid).collect(),
+            });
+        }
+        //Stable, so equally old images keep the listing order
+        matches.sort_by_key(|m| m.published_at);
+        matches
+            .pop()
+            .ok_or_else(|| ImgapiError::ImageNotFound(self.clone()))
+    }
+
+    /// Whether `manifest` is the image this identifier refers to.
+    pub fn matches(&self, manifest: &Manifest) -> bool {
+        match self {
+            ImageId::Uuid(uuid) => manifest.uuid == *uuid,
+            ImageId::Prefix(prefix) => manifest.uuid.to_string().starts_with(prefix.as_str()),
+            ImageId::NameVersion { name, version } => {
+                manifest.name == *name && manifest.version == *version
+            }
+        }
+    }
+}
+
+impl FromStr for ImageId {
+    type Err = ParseImageIdError;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        if let Some((name, version)) = s.split_once('@') {
+            if name.is_empty() || version.is_empty() {
+                return Err(ParseImageIdError(s.into()));
+            }
+            return Ok(ImageId::NameVersion {
+                name: name.into(),
+                version: version.into(),
+            });
+        }
+
+        if let Ok(uuid) = Uuid::parse_str(s) {
+            return Ok(ImageId::Uuid(uuid));
+        }
+
+        let is_prefix =
+            !s.is_empty() && s.len() < 36 && s.chars().all(|c| c.is_ascii_hexdigit() || c == '-');
+        if is_prefix {
+            Ok(ImageId::Prefix(s.to_ascii_lowercase()))
+        } else {
+            Err(ParseImageIdError(s.into()))
+        }
+    }
+}
+
+impl Display for ImageId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ImageId::Uuid(uuid) => write!(f, "{}", uuid),
+            ImageId::Prefix(prefix) => write!(f, "{}", prefix),
+            ImageId::NameVersion { name, version } => write!(f, "{}@{}", name, version),
+        }
+    }
+}
+
+impl From<Uuid> for ImageId {
+    fn from(value: Uuid) -> Self {
+        ImageId::Uuid(value)
+    }
+}
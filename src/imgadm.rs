@@ -0,0 +1,166 @@
+//! Installed images, as `imgadm` records them on SmartOS hosts.
+//!
+//! The on-host database keeps one `images/<zpool>-<uuid>.json` file per
+//! installed image below /var/imgadm, next to the `imgadm.conf` that holds
+//! the configured sources.
+
+use serde::{Deserialize, Serialize, Serializer};
+use std::fs;
+use std::path::{Path, PathBuf};
+use url::Url;
+use uuid::Uuid;
+
+use crate::error::Result;
+use crate::manifest::Manifest;
+use crate::ser::SerializeConfig;
+#[cfg(feature = "client")]
+use crate::sources::Sources;
+
+//Where SmartOS keeps the imgadm database.
+pub const IMGADM_DIR: &str = "/var/imgadm";
+
+#[doc = "An installed image record as printed by `imgadm list -j` and `imgadm get`"]
+#[derive(Deserialize, Serialize, Debug, Clone)]
+pub struct InstalledImage {
+    //The image manifest. imgadm leaves out unset fields instead of writing nulls.
+    #[serde(serialize_with = "serialize_manifest")]
+    pub manifest: Manifest,
+
+    //The zpool the image is installed in, e.g. "zones".
+    pub zpool: String,
+
+    //The image source the image was imported from, if known.
+    #[serde(skip_serializing_if = "Option::is_none", default)]
+    pub source: Option<Url>,
+
+    //Number of datasets cloned from this image.
+    #[serde(default)]
+    pub clones: u64,
+}
+
+impl InstalledImage {
+    pub fn new(manifest: Manifest, zpool: impl Into<String>) -> Self {
+        Self {
+            manifest,
+            zpool: zpool.into(),
+            source: None,
+            clones: 0,
+        }
+    }
+}
+
+fn serialize_manifest<S: Serializer>(
+    manifest: &Manifest,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    SerializeConfig::default()
+        .wrap(manifest)
+        .serialize(serializer)
+}
+
+/// Render `images` the way `imgadm list -j` does.
+pub fn list_json(images: &[InstalledImage]) -> serde_json::Result<String> {
+    serde_json::to_string_pretty(images)
+}
+
+//The stored form of an image, imgadm counts clones when listing.
+#[derive(Serialize)]
+struct Record<'a> {
+    #[serde(serialize_with = "serialize_manifest")]
+    manifest: &'a Manifest,
+    zpool: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    source: Option<&'a Url>,
+}
+
+#[doc = "The imgadm database of a host, /var/imgadm or a copy of it"]
+#[derive(Debug, Clone)]
+pub struct Database {
+    root: PathBuf,
+}
+
+impl Database {
+    pub fn open(root: impl Into<PathBuf>) -> Self {
+        Self { root: root.into() }
+    }
+
+    /// The database of the running host.
+    pub fn system() -> Self {
+        Self::open(IMGADM_DIR)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn image_path(&self, zpool: &str, uuid: Uuid) -> PathBuf {
+        self.root
+            .join("images")
+            .join(format!("{}-{}.json", zpool, uuid))
+    }
+
+    pub fn config_path(&self) -> PathBuf {
+        self.root.join("imgadm.conf")
+    }
+
+    /// All installed images, in no particular order. Their clones are not
+    /// counted, as that needs the zpool.
+    pub fn images(&self) -> Result<Vec<InstalledImage>> {
+        let entries = match fs::read_dir(self.root.join("images")) {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut images = vec![];
+        for entry in entries {
+            let path = entry?.path();
+            if path.extension().is_some_and(|ext| ext == "json") {
+                images.push(serde_json::from_slice(&fs::read(path)?)?);
+            }
+        }
+        Ok(images)
+    }
+
+    pub fn image(&self, zpool: &str, uuid: Uuid) -> Result<Option<InstalledImage>> {
+        match fs::read(self.image_path(zpool, uuid)) {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Record `image` as installed, replacing the file atomically.
+    pub fn put_image(&self, image: &InstalledImage) -> Result<()> {
+        let path = self.image_path(&image.zpool, image.manifest.uuid);
+        let record = Record {
+            manifest: &image.manifest,
+            zpool: &image.zpool,
+            source: image.source.as_ref(),
+        };
+        fs::create_dir_all(self.root.join("images"))?;
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_vec_pretty(&record)?)?;
+        fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    /// Returns whether the image was recorded.
+    pub fn remove_image(&self, zpool: &str, uuid: Uuid) -> Result<bool> {
+        match fs::remove_file(self.image_path(zpool, uuid)) {
+            Ok(()) => Ok(true),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    #[cfg(feature = "client")]
+    pub fn sources(&self) -> Result<Sources> {
+        Sources::load(self.config_path())
+    }
+
+    #[cfg(feature = "client")]
+    pub fn save_sources(&self, sources: &Sources) -> Result<()> {
+        sources.save(self.config_path())
+    }
+}
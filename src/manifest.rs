@@ -1,4 +1,5 @@
-use chrono::{DateTime, Utc};
+use crate::timestamp::Timestamp;
+use crate::units::{Bytes, MiB};
 use derive_builder::{Builder, UninitializedFieldError};
 use indexmap::IndexMap;
 use miette::Diagnostic;
@@ -10,6 +11,17 @@ use thiserror::Error;
 use url::Url;
 use uuid::Uuid;
 
+mod jsonl;
+mod migrate;
+mod validate;
+
+pub use jsonl::{read_jsonl, write_jsonl};
+pub use migrate::{migrate, Migrated, MigrationError, Transformation};
+pub use validate::{InvalidManifest, ManifestViolation};
+
+//The manifest format/spec version produced by this crate.
+pub const CURRENT_VERSION: i32 = 2;
+
 #[doc = "Error type for All zfs related builders"]
 #[derive(Debug, Error, Diagnostic)]
 #[non_exhaustive]
@@ -46,7 +58,7 @@ impl Display for ManifestBuilderError {
 #[builder(build_fn(error = "ManifestBuilderError"))]
 pub struct Manifest {
     //Version of the manifest format/spec. The current value is 2.
-    #[builder(setter(skip), default = "2")]
+    #[builder(setter(skip), default = "CURRENT_VERSION")]
     pub v: i32,
 
     //The unique identifier for a UUID. This is set by the IMGAPI server. See details below.
@@ -87,7 +99,7 @@ pub struct Manifest {
 
     //An object with details on image creation failure. It only exists when state=='failed'.
     #[builder(setter(into, strip_option), default)]
-    pub error: Option<Map<String, Value>>,
+    pub error: Option<ImageError>,
 
     //Indicates if this image is available for provisioning.
     #[builder(default = "false")]
@@ -98,10 +110,11 @@ pub struct Manifest {
     pub public: bool,
 
     //The date at which the image is activated. Set by the IMGAPI server.
+    #[serde(default)]
     #[builder(setter(into, strip_option), default)]
-    pub published_at: Option<DateTime<Utc>>,
+    pub published_at: Option<Timestamp>,
 
-    //The image type. One of "zone-dataset" for a ZFS dataset used to create a new SmartOS zone, "lx-dataset" for a Lx-brand image, "lxd" for a LXD image, "zvol" for a virtual machine image or "other" for image types that serve any other specific purpose.
+    //The image type. One of "zone-dataset" for a ZFS dataset used to create a new SmartOS zone, "lx-dataset" for a Lx-brand image, "lxd" for a LXD image, "zvol" for a virtual machine image, "docker" for a Docker image or "other" for image types that serve any other specific purpose.
     #[serde(rename = "type")]
     #[builder(setter(into), default)]
     pub image_type: ImageType,
@@ -116,7 +129,7 @@ pub struct Manifest {
 
     //An array of objects describing the image files.
     #[builder(default)]
-    pub files: Vec<Map<String, Value>>,
+    pub files: Vec<ImageFile>,
 
     //Access Control List. An array of account UUIDs given access to a private image. The field is only relevant to private images.
     #[builder(setter(into, strip_option), default)]
@@ -135,12 +148,14 @@ pub struct Manifest {
     pub billing_tags: Option<Vec<String>>,
 
     //An object that defines a collection of properties that is used by other APIs to evaluate where should customer VMs be placed.
+    //Older manifests sent an array of trait names, each is read as name: true.
+    #[serde(default, deserialize_with = "deserialize_traits")]
     #[builder(setter(into, strip_option), default)]
-    pub traits: Option<Vec<String>>,
+    pub traits: Option<IndexMap<String, TraitValue>>,
 
     //An object of key/value pairs that allows clients to categorize images by any given criteria.
     #[builder(setter(into, strip_option), default)]
-    pub tags: Option<IndexMap<String, String>>,
+    pub tags: Option<IndexMap<String, TagValue>>,
 
     //A boolean indicating whether to generate passwords for the users in the "users" field. If not present, the default value is true.
     #[builder(setter(into, strip_option), default)]
@@ -154,11 +169,240 @@ pub struct Manifest {
     #[builder(setter(into, strip_option), default)]
     pub channels: Option<Vec<String>>,
 
+    //The Linux kernel version emulated for lx-dataset images, e.g. "4.3.0".
+    #[serde(default)]
+    #[builder(setter(into, strip_option), default)]
+    pub kernel_version: Option<String>,
+
     #[serde(flatten)]
     #[builder(setter(into, strip_option), default)]
     pub vm_image_properties: Option<ImageVMProperties>,
 }
 
+//Keys of Manifest.files entries that are only visible to IMGAPI operators.
+pub(crate) const ADMIN_FILE_FIELDS: &[&str] = &["stor"];
+
+//Optional keys of Manifest.files entries, left out when unset unless nulls
+//are emitted, see ser::NullHandling.
+pub(crate) const OPTIONAL_FILE_FIELDS: &[&str] =
+    &["dataset_guid", "digest", "uncompressedDigest", "stor"];
+
+//Tags IMGAPI sets on images imported from a Docker registry.
+pub const DOCKER_ID_TAG: &str = "docker:id";
+pub const DOCKER_REPO_TAG: &str = "docker:repo";
+
+impl Manifest {
+    /// The admin-only fields of each file, all unset unless the manifest was
+    /// fetched with inclAdminFields.
+    pub fn admin_fields(&self) -> Vec<AdminFields> {
+        self.files.iter().map(|file| file.admin.clone()).collect()
+    }
+
+    /// The Docker image ID of an imported Docker image.
+    pub fn docker_id(&self) -> Option<&str> {
+        self.tag_str(DOCKER_ID_TAG)
+    }
+
+    /// The repository an imported Docker image was pulled from, e.g.
+    /// library/busybox.
+    pub fn docker_repo(&self) -> Option<&str> {
+        self.tag_str(DOCKER_REPO_TAG)
+    }
+
+    fn tag_str(&self, key: &str) -> Option<&str> {
+        self.tags.as_ref()?.get(key)?.as_str()
+    }
+
+    /// Copy of this manifest without tenant or operator data (acl, owner,
+    /// error details and admin-only file fields), safe for public display.
+    pub fn redacted(&self) -> Manifest {
+        let mut m = self.clone();
+        m.owner = uuid::Builder::nil().into_uuid();
+        m.acl = None;
+        m.error = None;
+        for file in m.files.iter_mut() {
+            file.admin = AdminFields::default();
+        }
+        m
+    }
+
+    /// Brand compatibility problems of the VM properties, judged against
+    /// `requirements.brand` and `requirements.bootrom`.
+    pub fn check_vm_properties(&self) -> Vec<IncompatibleVmProperties> {
+        let (Some(vm), Some(requirements)) = (&self.vm_image_properties, &self.requirements) else {
+            return vec![];
+        };
+        match &requirements.brand {
+            Some(brand) => vm.check_brand(brand, requirements.bootrom.as_ref()),
+            None => vec![],
+        }
+    }
+}
+
+impl ManifestBuilder {
+    /// Set traits in the old array shape, every name becomes a `true` trait.
+    #[deprecated(note = "traits is an object, use traits() with TraitValue entries")]
+    pub fn trait_names<S: Into<String>>(
+        &mut self,
+        names: impl IntoIterator<Item = S>,
+    ) -> &mut Self {
+        self.traits(
+            names
+                .into_iter()
+                .map(|name| (name.into(), TraitValue::Bool(true)))
+                .collect::<IndexMap<_, _>>(),
+        )
+    }
+}
+
+#[doc = "The value of a trait: a flag, a string or a list of strings"]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+#[serde(untagged)]
+pub enum TraitValue {
+    Bool(bool),
+    String(String),
+    List(Vec<String>),
+}
+
+impl From<bool> for TraitValue {
+    fn from(value: bool) -> Self {
+        TraitValue::Bool(value)
+    }
+}
+
+impl From<&str> for TraitValue {
+    fn from(value: &str) -> Self {
+        TraitValue::String(value.to_string())
+    }
+}
+
+impl From<String> for TraitValue {
+    fn from(value: String) -> Self {
+        TraitValue::String(value)
+    }
+}
+
+impl From<Vec<String>> for TraitValue {
+    fn from(value: Vec<String>) -> Self {
+        TraitValue::List(value)
+    }
+}
+
+fn deserialize_traits<'de, D>(
+    deserializer: D,
+) -> Result<Option<IndexMap<String, TraitValue>>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Traits {
+        Map(IndexMap<String, TraitValue>),
+        Names(Vec<String>),
+    }
+
+    Ok(
+        Option::<Traits>::deserialize(deserializer)?.map(|traits| match traits {
+            Traits::Map(map) => map,
+            Traits::Names(names) => names
+                .into_iter()
+                .map(|name| (name, TraitValue::Bool(true)))
+                .collect(),
+        }),
+    )
+}
+
+#[doc = "The value of a tag. Usually a string, but imports carry numbers, booleans and more"]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+#[serde(untagged)]
+pub enum TagValue {
+    String(String),
+    Bool(bool),
+    Number(serde_json::Number),
+    Other(Value),
+}
+
+impl TagValue {
+    /// The value if it is a string, see to_string() for any value.
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            TagValue::String(s) => Some(s),
+            _ => None,
+        }
+    }
+}
+
+//Strings as they are, anything else as JSON, e.g. true or 8080.
+impl Display for TagValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TagValue::String(s) => f.write_str(s),
+            TagValue::Bool(b) => write!(f, "{}", b),
+            TagValue::Number(n) => write!(f, "{}", n),
+            TagValue::Other(v) => write!(f, "{}", v),
+        }
+    }
+}
+
+impl From<&str> for TagValue {
+    fn from(value: &str) -> Self {
+        TagValue::String(value.to_string())
+    }
+}
+
+impl From<String> for TagValue {
+    fn from(value: String) -> Self {
+        TagValue::String(value)
+    }
+}
+
+impl From<bool> for TagValue {
+    fn from(value: bool) -> Self {
+        TagValue::Bool(value)
+    }
+}
+
+impl From<i64> for TagValue {
+    fn from(value: i64) -> Self {
+        TagValue::Number(value.into())
+    }
+}
+
+impl From<Value> for TagValue {
+    fn from(value: Value) -> Self {
+        match value {
+            Value::String(s) => TagValue::String(s),
+            Value::Bool(b) => TagValue::Bool(b),
+            Value::Number(n) => TagValue::Number(n),
+            other => TagValue::Other(other),
+        }
+    }
+}
+
+#[doc = "Why the creation of a failed image failed"]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq)]
+pub struct ImageError {
+    //A CamelCase error code, e.g. PrepareImageDidNotRun. Older servers may omit it.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub code: Option<String>,
+
+    #[serde(default)]
+    pub message: String,
+
+    //Other keys sent by the server, e.g. stack.
+    #[serde(flatten)]
+    pub extra: Map<String, Value>,
+}
+
+impl Display for ImageError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.code {
+            Some(code) => write!(f, "{}: {}", code, self.message),
+            None => f.write_str(&self.message),
+        }
+    }
+}
+
 #[derive(Default, Deserialize, Serialize, Debug, Clone, StrumDisplay, PartialEq, Eq)]
 #[serde(rename_all = "snake_case")]
 pub enum ImageState {
@@ -182,6 +426,8 @@ pub enum ImageType {
     Lxd,
     #[strum(serialize = "zvol")]
     Zvol,
+    #[strum(serialize = "docker")]
+    Docker,
     #[strum(serialize = "other")]
     Other,
 }
@@ -214,11 +460,11 @@ pub struct ImageRequirements {
 
     //Minimum RAM (in MiB) required to provision this image.
     #[builder(setter(into, strip_option), default)]
-    pub min_ram: Option<i64>,
+    pub min_ram: Option<MiB>,
 
     //Maximum RAM (in MiB) this image may be provisioned with.
     #[builder(setter(into, strip_option), default)]
-    pub max_ram: Option<i64>,
+    pub max_ram: Option<MiB>,
 
     //Minimum platform requirement for provisioning with this image.
     #[builder(setter(into, strip_option), default)]
@@ -239,8 +485,9 @@ pub struct RequirementNetworks {
     description: String,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, StrumDisplay)]
+#[derive(Deserialize, Serialize, Debug, Clone, StrumDisplay, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
 pub enum ImageRequirementBootRom {
     Bios,
     Uefi,
@@ -267,56 +514,237 @@ pub struct ImageVMProperties {
     pub cpu_type: String,
 
     //The size (in MiB) of this VM image's disk.
-    pub image_size: u64,
+    pub image_size: MiB,
+}
+
+#[doc = "Hypervisor a zvol image is meant to boot on, named after the zone brand"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, EnumString, StrumDisplay)]
+#[strum(serialize_all = "lowercase")]
+pub enum HvmTarget {
+    Bhyve,
+    Kvm,
+}
+
+impl HvmTarget {
+    //The disk and nic models vmadm accepts for the brand.
+    pub fn disk_drivers(&self) -> &'static [DiskDrivers] {
+        match self {
+            HvmTarget::Bhyve => &[DiskDrivers::Virtio, DiskDrivers::Ahci, DiskDrivers::Nvme],
+            HvmTarget::Kvm => &[DiskDrivers::Virtio, DiskDrivers::Ide, DiskDrivers::Scsi],
+        }
+    }
+
+    pub fn nic_drivers(&self) -> &'static [NetDrivers] {
+        match self {
+            HvmTarget::Bhyve => &[NetDrivers::Virtio, NetDrivers::E1000],
+            HvmTarget::Kvm => &[NetDrivers::Virtio, NetDrivers::E1000, NetDrivers::Rtl8139],
+        }
+    }
+
+    //cpu_type is a QEMU setting, bhyve has no restriction on it.
+    pub fn cpu_types(&self) -> Option<&'static [&'static str]> {
+        match self {
+            HvmTarget::Bhyve => None,
+            HvmTarget::Kvm => Some(&["qemu64", "host"]),
+        }
+    }
+
+    //bhyve images must say how they boot, KVM only knows its built in BIOS.
+    pub fn requires_bootrom(&self) -> bool {
+        matches!(self, HvmTarget::Bhyve)
+    }
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, EnumString, StrumDisplay)]
+#[derive(Debug, Clone, Error, Diagnostic, PartialEq, Eq)]
+pub enum IncompatibleVmProperties {
+    #[error("{target} does not support the {driver} disk driver")]
+    #[diagnostic(code(imgapi::vm::disk_driver))]
+    DiskDriver {
+        target: HvmTarget,
+        driver: DiskDrivers,
+    },
+
+    #[error("{target} does not support the {driver} nic driver")]
+    #[diagnostic(code(imgapi::vm::nic_driver))]
+    NicDriver {
+        target: HvmTarget,
+        driver: NetDrivers,
+    },
+
+    #[error("{target} does not support the {bootrom} bootrom")]
+    #[diagnostic(
+        code(imgapi::vm::bootrom),
+        help("bootrom selection is only available for bhyve")
+    )]
+    Bootrom {
+        target: HvmTarget,
+        bootrom: ImageRequirementBootRom,
+    },
+
+    #[error("{target} images must set requirements.bootrom")]
+    #[diagnostic(code(imgapi::vm::missing_bootrom))]
+    MissingBootrom { target: HvmTarget },
+
+    #[error("{target} does not support cpu_type {cpu_type:?}")]
+    #[diagnostic(code(imgapi::vm::cpu_type))]
+    CpuType { target: HvmTarget, cpu_type: String },
+}
+
+impl ImageVMProperties {
+    /// Check that the drivers, cpu type and bootrom can boot on `target`.
+    pub fn check_target(
+        &self,
+        target: HvmTarget,
+        bootrom: Option<&ImageRequirementBootRom>,
+    ) -> Vec<IncompatibleVmProperties> {
+        let mut problems = vec![];
+        if !target.disk_drivers().contains(&self.disk_driver) {
+            problems.push(IncompatibleVmProperties::DiskDriver {
+                target,
+                driver: self.disk_driver.clone(),
+            });
+        }
+        if !target.nic_drivers().contains(&self.nic_driver) {
+            problems.push(IncompatibleVmProperties::NicDriver {
+                target,
+                driver: self.nic_driver.clone(),
+            });
+        }
+        if target
+            .cpu_types()
+            .is_some_and(|types| !types.contains(&self.cpu_type.as_str()))
+        {
+            problems.push(IncompatibleVmProperties::CpuType {
+                target,
+                cpu_type: self.cpu_type.clone(),
+            });
+        }
+        match bootrom {
+            None if target.requires_bootrom() => {
+                problems.push(IncompatibleVmProperties::MissingBootrom { target })
+            }
+            Some(bootrom) if !target.requires_bootrom() => {
+                problems.push(IncompatibleVmProperties::Bootrom {
+                    target,
+                    bootrom: bootrom.clone(),
+                })
+            }
+            _ => {}
+        }
+        problems
+    }
+
+    /// Same as [`ImageVMProperties::check_target`] for a brand name such as
+    /// "bhyve" or "kvm". Unknown brands are not checked.
+    pub fn check_brand(
+        &self,
+        brand: &str,
+        bootrom: Option<&ImageRequirementBootRom>,
+    ) -> Vec<IncompatibleVmProperties> {
+        match brand.parse() {
+            Ok(target) => self.check_target(target, bootrom),
+            Err(_) => vec![],
+        }
+    }
+}
+
+impl ImageVMPropertiesBuilder {
+    /// Builder with drivers and cpu type known to boot on `target`; only
+    /// `image_size` is left to set.
+    pub fn preset(target: HvmTarget) -> Self {
+        let mut builder = Self::default();
+        builder
+            .nic_driver(target.nic_drivers()[0].clone())
+            .disk_driver(target.disk_drivers()[0].clone())
+            .cpu_type(target.cpu_types().map_or("host", |types| types[0]));
+        builder
+    }
+}
+
+impl ImageRequirementsBuilder {
+    /// Requirements pinning the image to `target`, booting bhyve via UEFI.
+    pub fn preset(target: HvmTarget) -> Self {
+        let mut builder = Self::default();
+        builder.brand(target.to_string());
+        if target.requires_bootrom() {
+            builder.bootrom(ImageRequirementBootRom::Uefi);
+        }
+        builder
+    }
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, EnumString, StrumDisplay, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 #[strum(serialize_all = "kebab-case")]
 pub enum NetDrivers {
     Virtio,
+    E1000,
+    Rtl8139,
+    //Not a vmadm model, kept so older manifests still parse.
     E1000g0,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, EnumString, StrumDisplay)]
+#[derive(Deserialize, Serialize, Debug, Clone, EnumString, StrumDisplay, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
 #[strum(serialize_all = "kebab-case")]
 pub enum DiskDrivers {
     Virtio,
+    Ide,
+    Scsi,
+    Ahci,
+    Nvme,
+    //Not a vmadm model, kept so older manifests still parse.
     Sata,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, Builder)]
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Builder)]
 pub struct ImageFile {
     //SHA-1 hex digest of the file content. Used for upload/download corruption checking.
     pub sha1: String,
 
     //Number of bytes. Maximum 20GiB. This maximum is meant to be a "you'll never hit it" cap, the purpose is to inform cache handling in IMGAPI servers.
-    pub size: i64,
+    pub size: Bytes,
 
     //The type of file compression used by the file. One of 'bzip2', 'gzip', 'none'.
     pub compression: ImageFileCompression,
 
     //Optional. The ZFS internal unique identifier for this dataset's snapshot (available via zfs get guid SNAPSHOT, e.g. zfs get guid zones/f669428c-a939-11e2-a485-b790efc0f0c1@final). If available, this is used to ensure a common base snapshot for incremental images (via imgadm create -i) and VM migrations (via vmadm send/receive).
+    #[serde(default)]
     #[builder(setter(into, strip_option), default)]
     pub dataset_guid: Option<String>,
 
-    //Only included if ?inclAdminFields=true is passed to GetImage/ListImages. The IMGAPI storage type used to store this file.
-    #[builder(setter(into, strip_option), default)]
-    pub stor: Option<String>,
+    //Only included if ?inclAdminFields=true is passed to GetImage/ListImages.
+    #[serde(flatten)]
+    #[builder(default)]
+    pub admin: AdminFields,
 
     //Optional. Docker digest of the file contents. Only used when manifest.type is 'docker'. This field gets set automatically by the AdminImportDockerImage call.
+    #[serde(default)]
     #[builder(setter(into, strip_option), default)]
     pub digest: Option<String>,
 
     //Optional. Docker digest of the uncompressed file contents. Only used when manifest.type is 'docker'. This field gets set automatically by the AdminImportDockerImage call. Note that this field will be removed in a future version of IMGAPI.
-    #[serde(rename = "uncompressedDigest")]
+    #[serde(rename = "uncompressedDigest", default)]
     #[builder(setter(into, strip_option), default)]
     pub uncompressed_digest: Option<String>,
+
+    //Keys this crate does not know yet, kept so manifests round trip.
+    #[serde(flatten)]
+    #[builder(default)]
+    pub other: Map<String, Value>,
 }
 
-#[derive(Deserialize, Serialize, Debug, Clone, StrumDisplay)]
+#[doc = "Operator-only file data, see ListImagesQuery.incl_admin_fields"]
+#[derive(Deserialize, Serialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct AdminFields {
+    //The IMGAPI storage type used to store this file, e.g. local or manta.
+    #[serde(default)]
+    pub stor: Option<String>,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, StrumDisplay, PartialEq, Eq)]
 #[serde(rename_all = "kebab-case")]
+#[strum(serialize_all = "kebab-case")]
 pub enum ImageFileCompression {
     Bzip2,
     Gzip,
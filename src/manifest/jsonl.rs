@@ -0,0 +1,28 @@
+use std::io::{BufRead, Write};
+
+use super::Manifest;
+use crate::error::Result;
+
+/// Read manifests from JSON Lines input, one manifest per line. Blank lines
+/// are skipped.
+pub fn read_jsonl<R: BufRead>(reader: R) -> impl Iterator<Item = Result<Manifest>> {
+    reader.lines().filter_map(|line| match line {
+        Ok(line) if line.trim().is_empty() => None,
+        Ok(line) => Some(serde_json::from_str(&line).map_err(Into::into)),
+        Err(err) => Some(Err(err.into())),
+    })
+}
+
+/// Write `manifests` as JSON Lines, one manifest per line.
+pub fn write_jsonl<'a, W, I>(mut writer: W, manifests: I) -> Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = &'a Manifest>,
+{
+    for manifest in manifests {
+        serde_json::to_writer(&mut writer, manifest)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.flush()?;
+    Ok(())
+}
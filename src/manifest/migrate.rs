@@ -0,0 +1,283 @@
+use miette::Diagnostic;
+use serde_json::{Map, Value};
+use std::fmt::Display;
+use thiserror::Error;
+
+use super::CURRENT_VERSION;
+
+//Fields of v=1 manifests that have no equivalent in the current spec.
+const DROPPED_FIELDS: &[&str] = &[
+    "creator_name",
+    "vendor_uuid",
+    "cloud_name",
+    "platform_type",
+    "urn",
+    "created_at",
+    "updated_at",
+];
+
+//Fields of v=1 file entries that the IMGAPI server now derives itself.
+const DROPPED_FILE_FIELDS: &[&str] = &["path", "url"];
+
+//Top level boolean fields that old tooling sometimes wrote as strings.
+const BOOLEAN_FIELDS: &[&str] = &[
+    "disabled",
+    "public",
+    "icon",
+    "generate_password",
+    "generate_passwords",
+];
+
+#[derive(Debug, Error, Diagnostic)]
+pub enum MigrationError {
+    #[error("manifest must be a JSON object")]
+    NotAnObject,
+
+    #[error("manifest field v has an invalid value: {0}")]
+    InvalidVersion(Value),
+
+    #[error(
+        "unsupported target manifest version {0}, the newest known version is {CURRENT_VERSION}"
+    )]
+    UnsupportedVersion(i32),
+
+    #[error("cannot downgrade a v{from} manifest to v{to}")]
+    Downgrade { from: i32, to: i32 },
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transformation {
+    Renamed {
+        from: String,
+        to: String,
+    },
+    Removed {
+        field: String,
+    },
+    Converted {
+        field: String,
+        from: Value,
+        to: Value,
+    },
+    Set {
+        field: String,
+        value: Value,
+    },
+}
+
+impl Display for Transformation {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Transformation::Renamed { from, to } => write!(f, "renamed {} to {}", from, to),
+            Transformation::Removed { field } => write!(f, "removed {}", field),
+            Transformation::Converted { field, from, to } => {
+                write!(f, "converted {} from {} to {}", field, from, to)
+            }
+            Transformation::Set { field, value } => write!(f, "set {} to {}", field, value),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Migrated {
+    //The upgraded manifest, ready to be deserialized into a Manifest.
+    pub manifest: Value,
+
+    //Every change made to the input, in the order it was applied.
+    pub transformations: Vec<Transformation>,
+}
+
+/// Upgrade a raw manifest to spec revision `target_v`.
+///
+/// Manifests without a `v` field are treated as v=1. Legacy compression
+/// names and string booleans are normalized regardless of the version.
+pub fn migrate(value: Value, target_v: i32) -> Result<Migrated, MigrationError> {
+    if !(1..=CURRENT_VERSION).contains(&target_v) {
+        return Err(MigrationError::UnsupportedVersion(target_v));
+    }
+
+    let Value::Object(mut obj) = value else {
+        return Err(MigrationError::NotAnObject);
+    };
+
+    let from = match obj.get("v") {
+        None => 1,
+        Some(v) => v
+            .as_i64()
+            .and_then(|v| i32::try_from(v).ok())
+            .ok_or_else(|| MigrationError::InvalidVersion(v.clone()))?,
+    };
+    if from > target_v {
+        return Err(MigrationError::Downgrade { from, to: target_v });
+    }
+
+    let mut transformations = vec![];
+    if from < 2 && target_v >= 2 {
+        upgrade_v1_to_v2(&mut obj, &mut transformations);
+    }
+    normalize_booleans(&mut obj, &mut transformations);
+    normalize_files(&mut obj, &mut transformations);
+    normalize_traits(&mut obj, &mut transformations);
+
+    Ok(Migrated {
+        manifest: Value::Object(obj),
+        transformations,
+    })
+}
+
+fn upgrade_v1_to_v2(obj: &mut Map<String, Value>, transformations: &mut Vec<Transformation>) {
+    if let Some(owner) = obj.remove("creator_uuid") {
+        obj.insert("owner".into(), owner);
+        transformations.push(Transformation::Renamed {
+            from: "creator_uuid".into(),
+            to: "owner".into(),
+        });
+    }
+
+    if let Some(restricted_to) = obj.remove("restricted_to_uuid") {
+        obj.insert("acl".into(), Value::Array(vec![restricted_to]));
+        transformations.push(Transformation::Renamed {
+            from: "restricted_to_uuid".into(),
+            to: "acl".into(),
+        });
+        set(obj, transformations, "public", Value::Bool(false));
+    }
+
+    if !obj.contains_key("published_at") {
+        if let Some(created_at) = obj.get("created_at").cloned() {
+            set(obj, transformations, "published_at", created_at);
+        }
+    }
+
+    for field in DROPPED_FIELDS {
+        if obj.remove(*field).is_some() {
+            transformations.push(Transformation::Removed {
+                field: field.to_string(),
+            });
+        }
+    }
+
+    //DSAPI only served active, enabled images and everything not restricted was public
+    if !obj.contains_key("state") {
+        set(
+            obj,
+            transformations,
+            "state",
+            Value::String("active".into()),
+        );
+    }
+    if !obj.contains_key("disabled") {
+        set(obj, transformations, "disabled", Value::Bool(false));
+    }
+    if !obj.contains_key("public") {
+        set(obj, transformations, "public", Value::Bool(true));
+    }
+
+    set(obj, transformations, "v", Value::from(2));
+}
+
+fn normalize_booleans(obj: &mut Map<String, Value>, transformations: &mut Vec<Transformation>) {
+    for field in BOOLEAN_FIELDS {
+        if let Some(value) = obj.get_mut(*field) {
+            convert_boolean(field, value, transformations);
+        }
+    }
+
+    if let Some(Value::Object(requirements)) = obj.get_mut("requirements") {
+        if let Some(value) = requirements.get_mut("ssh_key") {
+            convert_boolean("requirements.ssh_key", value, transformations);
+        }
+    }
+}
+
+fn normalize_files(obj: &mut Map<String, Value>, transformations: &mut Vec<Transformation>) {
+    let Some(Value::Array(files)) = obj.get_mut("files") else {
+        return;
+    };
+
+    for (idx, file) in files.iter_mut().enumerate() {
+        let Value::Object(file) = file else {
+            continue;
+        };
+
+        //Typed files need a compression, DSAPI datasets only had it in the file name
+        if !file.contains_key("compression") {
+            let path = file.get("path").and_then(Value::as_str).unwrap_or_default();
+            let inferred = if path.ends_with(".bz2") {
+                "bzip2"
+            } else if path.ends_with(".gz") {
+                "gzip"
+            } else {
+                "none"
+            };
+            file.insert("compression".into(), Value::String(inferred.into()));
+            transformations.push(Transformation::Set {
+                field: format!("files[{}].compression", idx),
+                value: Value::String(inferred.into()),
+            });
+        }
+
+        for field in DROPPED_FILE_FIELDS {
+            if file.remove(*field).is_some() {
+                transformations.push(Transformation::Removed {
+                    field: format!("files[{}].{}", idx, field),
+                });
+            }
+        }
+
+        if let Some(compression) = file.get_mut("compression") {
+            let normalized = match compression.as_str() {
+                Some("bz2") | Some("bzip") => "bzip2",
+                Some("gz") | Some("gzip2") => "gzip",
+                Some("") => "none",
+                _ => continue,
+            };
+            let from = std::mem::replace(compression, Value::String(normalized.into()));
+            transformations.push(Transformation::Converted {
+                field: format!("files[{}].compression", idx),
+                from,
+                to: compression.clone(),
+            });
+        }
+    }
+}
+
+//traits used to be an array of names, it is an object of name: value now.
+fn normalize_traits(obj: &mut Map<String, Value>, transformations: &mut Vec<Transformation>) {
+    let Some(Value::Array(names)) = obj.get("traits") else {
+        return;
+    };
+    let traits = names
+        .iter()
+        .filter_map(Value::as_str)
+        .map(|name| (name.to_string(), Value::Bool(true)))
+        .collect();
+    set(obj, transformations, "traits", Value::Object(traits));
+}
+
+fn convert_boolean(field: &str, value: &mut Value, transformations: &mut Vec<Transformation>) {
+    let converted = match value.as_str().map(|s| s.to_ascii_lowercase()).as_deref() {
+        Some("true") => true,
+        Some("false") => false,
+        _ => return,
+    };
+    let from = std::mem::replace(value, Value::Bool(converted));
+    transformations.push(Transformation::Converted {
+        field: field.into(),
+        from,
+        to: value.clone(),
+    });
+}
+
+fn set(
+    obj: &mut Map<String, Value>,
+    transformations: &mut Vec<Transformation>,
+    field: &str,
+    value: Value,
+) {
+    obj.insert(field.into(), value.clone());
+    transformations.push(Transformation::Set {
+        field: field.into(),
+        value,
+    });
+}
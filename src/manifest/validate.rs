@@ -0,0 +1,195 @@
+use miette::Diagnostic;
+use thiserror::Error;
+
+use super::{ImageState, ImageType, Manifest, DOCKER_ID_TAG, DOCKER_REPO_TAG};
+
+//Limits of the IMGAPI manifest spec.
+const MAX_NAME_LEN: usize = 512;
+const MAX_VERSION_LEN: usize = 128;
+const MAX_DESCRIPTION_LEN: usize = 512;
+
+//IMGAPI only accepts a single file per image, of at most 20 GiB.
+const MAX_FILES: usize = 1;
+const MAX_FILE_SIZE: u64 = 20 * 1024 * 1024 * 1024;
+
+#[derive(Debug, Error, Diagnostic)]
+#[error("manifest has {} spec violation(s)", violations.len())]
+#[diagnostic(code(imgapi::manifest::invalid))]
+pub struct InvalidManifest {
+    #[related]
+    pub violations: Vec<ManifestViolation>,
+}
+
+#[derive(Debug, Clone, Error, Diagnostic, PartialEq, Eq)]
+pub enum ManifestViolation {
+    #[error("name must be 1 to {MAX_NAME_LEN} characters, not {0}")]
+    #[diagnostic(code(imgapi::manifest::name))]
+    NameLength(usize),
+
+    #[error("name contains {0:?}")]
+    #[diagnostic(
+        code(imgapi::manifest::name),
+        help("names may contain letters, digits, spaces and '-', '_', '.', '/'")
+    )]
+    NameCharacter(char),
+
+    #[error("version must be 1 to {MAX_VERSION_LEN} characters, not {0}")]
+    #[diagnostic(code(imgapi::manifest::version))]
+    VersionLength(usize),
+
+    #[error("version contains {0:?}")]
+    #[diagnostic(
+        code(imgapi::manifest::version),
+        help("versions may contain letters, digits and '-', '_', '.', '/'")
+    )]
+    VersionCharacter(char),
+
+    #[error("description must be at most {MAX_DESCRIPTION_LEN} characters, not {0}")]
+    #[diagnostic(code(imgapi::manifest::description))]
+    DescriptionLength(usize),
+
+    #[error("v={0} manifests must have a uuid")]
+    #[diagnostic(code(imgapi::manifest::uuid))]
+    MissingUuid(i32),
+
+    #[error("v={0} manifests must have an owner")]
+    #[diagnostic(code(imgapi::manifest::owner))]
+    MissingOwner(i32),
+
+    #[error("image is its own origin")]
+    #[diagnostic(code(imgapi::manifest::origin))]
+    OwnOrigin,
+
+    #[error("zvol images must set nic_driver, disk_driver, cpu_type and image_size")]
+    #[diagnostic(code(imgapi::manifest::vm_properties))]
+    MissingVmProperties,
+
+    #[error("docker images must have a {0} tag")]
+    #[diagnostic(code(imgapi::manifest::docker))]
+    MissingDockerTag(&'static str),
+
+    #[error("active images must have a file")]
+    #[diagnostic(code(imgapi::manifest::files))]
+    NoFiles,
+
+    #[error("images have at most {MAX_FILES} file, not {0}")]
+    #[diagnostic(code(imgapi::manifest::files))]
+    TooManyFiles(usize),
+
+    #[error("files[{index}] has an invalid {field}")]
+    #[diagnostic(code(imgapi::manifest::files))]
+    InvalidFileField { index: usize, field: &'static str },
+}
+
+impl Manifest {
+    /// Check the manifest against the rules of the IMGAPI spec, reporting
+    /// every violation instead of stopping at the first.
+    pub fn validate(&self) -> Result<(), InvalidManifest> {
+        self.check(true)
+    }
+
+    /// Like validate, for a manifest not yet created on a server, e.g. one
+    /// from ManifestBuilder. The uuid and owner the server assigns are not
+    /// required.
+    pub fn validate_draft(&self) -> Result<(), InvalidManifest> {
+        self.check(false)
+    }
+
+    fn check(&self, created: bool) -> Result<(), InvalidManifest> {
+        use ManifestViolation::*;
+        let mut violations = vec![];
+
+        let name_len = self.name.chars().count();
+        if name_len == 0 || name_len > MAX_NAME_LEN {
+            violations.push(NameLength(name_len));
+        }
+        if let Some(c) = self
+            .name
+            .chars()
+            .find(|c| !is_version_char(*c) && *c != ' ')
+        {
+            violations.push(NameCharacter(c));
+        }
+        let version_len = self.version.chars().count();
+        if version_len == 0 || version_len > MAX_VERSION_LEN {
+            violations.push(VersionLength(version_len));
+        }
+        if let Some(c) = self.version.chars().find(|c| !is_version_char(*c)) {
+            violations.push(VersionCharacter(c));
+        }
+        let description_len = self.description.as_ref().map_or(0, |d| d.chars().count());
+        if description_len > MAX_DESCRIPTION_LEN {
+            violations.push(DescriptionLength(description_len));
+        }
+
+        if created && self.v >= 2 {
+            if self.uuid.is_nil() {
+                violations.push(MissingUuid(self.v));
+            }
+            if self.owner.is_nil() {
+                violations.push(MissingOwner(self.v));
+            }
+        }
+        if self.origin.is_some_and(|origin| origin == self.uuid) {
+            violations.push(OwnOrigin);
+        }
+        if self.image_type == ImageType::Zvol && self.vm_image_properties.is_none() {
+            violations.push(MissingVmProperties);
+        }
+
+        if self.image_type == ImageType::Docker {
+            if self.docker_id().is_none() {
+                violations.push(MissingDockerTag(DOCKER_ID_TAG));
+            }
+            if self.docker_repo().is_none() {
+                violations.push(MissingDockerTag(DOCKER_REPO_TAG));
+            }
+        }
+
+        if self.files.is_empty() && self.state == ImageState::Active {
+            violations.push(NoFiles);
+        }
+        if self.files.len() > MAX_FILES {
+            violations.push(TooManyFiles(self.files.len()));
+        }
+        for (index, file) in self.files.iter().enumerate() {
+            if file.sha1.len() != 40 || !file.sha1.bytes().all(|b| b.is_ascii_hexdigit()) {
+                violations.push(InvalidFileField {
+                    index,
+                    field: "sha1",
+                });
+            }
+            //Docker layers are fetched from the registry by this digest
+            if self.image_type == ImageType::Docker
+                && !file.digest.as_deref().is_some_and(is_sha256_digest)
+            {
+                violations.push(InvalidFileField {
+                    index,
+                    field: "digest",
+                });
+            }
+            if file.size.0 > MAX_FILE_SIZE {
+                violations.push(InvalidFileField {
+                    index,
+                    field: "size",
+                });
+            }
+        }
+
+        if violations.is_empty() {
+            Ok(())
+        } else {
+            Err(InvalidManifest { violations })
+        }
+    }
+}
+
+fn is_sha256_digest(digest: &str) -> bool {
+    digest
+        .strip_prefix("sha256:")
+        .is_some_and(|hex| hex.len() == 64 && hex.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+fn is_version_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || matches!(c, '-' | '_' | '.' | '/')
+}
@@ -0,0 +1,172 @@
+use serde::{ser::Error as _, Serialize, Serializer};
+use serde_json::Value;
+
+use crate::manifest::{ADMIN_FILE_FIELDS, OPTIONAL_FILE_FIELDS};
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum NullHandling {
+    //Leave out object keys whose value is null.
+    #[default]
+    Skip,
+    //Write unset optional fields of a manifest, its requirements and files
+    //as explicit nulls. Error details are written as the server sent them.
+    Emit,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum Audience {
+    //Everything, like IMGAPI responses with inclAdminFields=true.
+    #[default]
+    Admin,
+    //Leave out operator-only data such as files[].stor.
+    Public,
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct SerializeConfig {
+    pub nulls: NullHandling,
+    pub audience: Audience,
+    //Sort the keys of every object (tags, traits, platform maps, ...) so the
+    //output is byte-stable across runs, e.g. for diffing or signing.
+    pub sort_keys: bool,
+}
+
+impl SerializeConfig {
+    pub fn nulls(mut self, nulls: NullHandling) -> Self {
+        self.nulls = nulls;
+        self
+    }
+
+    pub fn audience(mut self, audience: Audience) -> Self {
+        self.audience = audience;
+        self
+    }
+
+    pub fn sort_keys(mut self, sort_keys: bool) -> Self {
+        self.sort_keys = sort_keys;
+        self
+    }
+
+    /// Wrap `value` so it serializes according to this config.
+    pub fn wrap<T: Serialize>(self, value: &T) -> WithConfig<'_, T> {
+        WithConfig {
+            value,
+            config: self,
+        }
+    }
+
+    /// Serialize `value` into a [`Value`] according to this config.
+    pub fn to_value<T: Serialize>(self, value: &T) -> serde_json::Result<Value> {
+        serde_json::to_value(self.wrap(value))
+    }
+}
+
+/// Serializer wrapper applying a [`SerializeConfig`] to any serializable type,
+/// most usefully `Manifest`.
+///
+/// ```
+/// use imgapi::manifest::ManifestBuilder;
+/// use imgapi::ser::{NullHandling, SerializeConfig};
+///
+/// let m = ManifestBuilder::default().name("base").version("1.0").build().unwrap();
+/// let skipped = serde_json::to_value(SerializeConfig::default().wrap(&m)).unwrap();
+/// assert!(skipped.get("description").is_none());
+///
+/// let emitted = SerializeConfig::default().nulls(NullHandling::Emit).to_value(&m).unwrap();
+/// assert!(emitted["description"].is_null());
+/// ```
+#[derive(Debug, Clone, Copy)]
+pub struct WithConfig<'a, T> {
+    value: &'a T,
+    config: SerializeConfig,
+}
+
+impl<T: Serialize> Serialize for WithConfig<'_, T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut value = serde_json::to_value(self.value).map_err(S::Error::custom)?;
+        if self.config.nulls == NullHandling::Skip {
+            strip_nulls(&mut value);
+        }
+        if self.config.audience == Audience::Public {
+            strip_admin_fields(&mut value);
+        }
+        if self.config.sort_keys {
+            sort_keys(&mut value);
+        }
+        value.serialize(serializer)
+    }
+}
+
+//Only unset fields of the crate's own structs: the top level of a manifest
+//or of each manifest in a list, its requirements and the optional fields of
+//its files. Nulls in user data, e.g. tags, error details or unknown file
+//keys, are kept.
+fn strip_nulls(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            map.retain(|_, v| !v.is_null());
+            if let Some(Value::Object(requirements)) = map.get_mut("requirements") {
+                requirements.retain(|_, v| !v.is_null());
+            }
+            if let Some(Value::Array(files)) = map.get_mut("files") {
+                for file in files.iter_mut().filter_map(Value::as_object_mut) {
+                    file.retain(|k, v| {
+                        !(v.is_null() && OPTIONAL_FILE_FIELDS.contains(&k.as_str()))
+                    });
+                }
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(strip_nulls),
+        _ => {}
+    }
+}
+
+//Applies to a manifest or a list of manifests.
+fn strip_admin_fields(value: &mut Value) {
+    match value {
+        Value::Object(manifest) => {
+            if let Some(Value::Array(files)) = manifest.get_mut("files") {
+                for file in files.iter_mut().filter_map(Value::as_object_mut) {
+                    for field in ADMIN_FILE_FIELDS {
+                        file.remove(*field);
+                    }
+                }
+            }
+        }
+        Value::Array(manifests) => manifests.iter_mut().for_each(strip_admin_fields),
+        _ => {}
+    }
+}
+
+fn sort_keys(value: &mut Value) {
+    match value {
+        Value::Object(map) => {
+            let mut entries: Vec<_> = std::mem::take(map).into_iter().collect();
+            entries.sort_by(|(a, _), (b, _)| a.cmp(b));
+            for (k, mut v) in entries {
+                sort_keys(&mut v);
+                map.insert(k, v);
+            }
+        }
+        Value::Array(values) => values.iter_mut().for_each(sort_keys),
+        _ => {}
+    }
+}
+
+/// The name a unit enum variant has on the wire, e.g. `zone-dataset` for
+/// ImageType::ZoneDataset. Empty for values that are not plain strings.
+#[cfg(any(feature = "client", feature = "sqlite"))]
+pub(crate) fn wire_name<T: Serialize>(value: &T) -> String {
+    match serde_json::to_value(value) {
+        Ok(Value::String(s)) => s,
+        _ => String::new(),
+    }
+}
+
+/// The value with the wire `name`, see wire_name.
+#[cfg(feature = "sqlite")]
+pub(crate) fn from_wire_name<T: serde::de::DeserializeOwned>(
+    name: String,
+) -> serde_json::Result<T> {
+    serde_json::from_value(Value::String(name))
+}
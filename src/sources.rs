@@ -0,0 +1,270 @@
+//! Configured image sources, like `imgadm sources`, stored in the sources
+//! key of an imgadm.conf style JSON file.
+
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use serde_json::{Map, Value};
+use std::collections::HashSet;
+use std::fmt::Debug;
+use std::path::Path;
+use strum::{Display as StrumDisplay, EnumString};
+use tokio::io::{AsyncWrite, AsyncWriteExt};
+use url::Url;
+use uuid::Uuid;
+
+use crate::client::{api_error, Client, ClientBuilder, Digests, MultiDigest};
+use crate::error::{ImgapiError, Result};
+use crate::id::ImageId;
+use crate::manifest::Manifest;
+
+mod docker;
+mod dsapi;
+mod file_repo;
+
+pub use docker::DockerSource;
+pub use dsapi::DsapiSource;
+pub use file_repo::FileRepo;
+
+//Where SmartOS keeps the imgadm configuration.
+pub const IMGADM_CONFIG: &str = "/var/imgadm/imgadm.conf";
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, EnumString, StrumDisplay, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+#[strum(serialize_all = "lowercase")]
+pub enum SourceType {
+    Imgapi,
+    Docker,
+    //The predecessor of IMGAPI, e.g. https://datasets.joyent.com/datasets.
+    Dsapi,
+}
+
+#[derive(Deserialize, Serialize, Debug, Clone, PartialEq, Eq)]
+pub struct SourceConfig {
+    pub url: Url,
+
+    #[serde(rename = "type")]
+    pub source_type: SourceType,
+
+    //Skip TLS certificate verification, for lab servers with self-signed certs.
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub insecure: bool,
+}
+
+impl SourceConfig {
+    pub fn imgapi(url: Url) -> Self {
+        Self {
+            url,
+            source_type: SourceType::Imgapi,
+            insecure: false,
+        }
+    }
+}
+
+/// Where images come from, so import code can treat IMGAPI servers, DSAPI
+/// servers and Docker registries alike. Operations a kind of source cannot
+/// do fail with [`ImgapiError::Unsupported`].
+pub trait Source: Send + Sync + Debug {
+    fn url(&self) -> &Url;
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<Manifest>>>;
+
+    fn get(&self, uuid: Uuid) -> BoxFuture<'_, Result<Manifest>>;
+
+    /// The image `id` refers to, looked up in the listing unless it is a
+    /// UUID, see [`ImageId::select`].
+    fn resolve<'a>(&'a self, id: &'a ImageId) -> BoxFuture<'a, Result<Manifest>> {
+        async move {
+            match id {
+                ImageId::Uuid(uuid) => self.get(*uuid).await,
+                id => id.select(self.list().await?),
+            }
+        }
+        .boxed()
+    }
+
+    /// Write file `index` of `manifest` to `writer`, verified against the
+    /// checksums of the manifest. Returns the number of bytes written.
+    fn fetch_file<'a>(
+        &'a self,
+        manifest: &'a Manifest,
+        index: usize,
+        writer: &'a mut (dyn AsyncWrite + Unpin + Send),
+    ) -> BoxFuture<'a, Result<u64>>;
+}
+
+impl Source for Client {
+    fn url(&self) -> &Url {
+        self.base_url()
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<Manifest>>> {
+        self.list_images().boxed()
+    }
+
+    fn get(&self, uuid: Uuid) -> BoxFuture<'_, Result<Manifest>> {
+        self.get_image(uuid).boxed()
+    }
+
+    fn resolve<'a>(&'a self, id: &'a ImageId) -> BoxFuture<'a, Result<Manifest>> {
+        Client::resolve(self, id).boxed()
+    }
+
+    fn fetch_file<'a>(
+        &'a self,
+        manifest: &'a Manifest,
+        index: usize,
+        mut writer: &'a mut (dyn AsyncWrite + Unpin + Send),
+    ) -> BoxFuture<'a, Result<u64>> {
+        async move {
+            self.fetch_file(manifest, index, &mut writer, &mut |_| {})
+                .await
+        }
+        .boxed()
+    }
+}
+
+#[doc = "An image of a merged listing across sources"]
+#[derive(Debug, Clone)]
+pub struct SourcedImage {
+    pub manifest: Manifest,
+    pub source: Url,
+}
+
+#[doc = "An ordered list of image sources, earlier sources take precedence"]
+#[derive(Deserialize, Serialize, Debug, Clone, Default)]
+pub struct Sources {
+    #[serde(default)]
+    sources: Vec<SourceConfig>,
+
+    //The rest of the configuration file, kept as is when saving.
+    #[serde(flatten)]
+    other: Map<String, Value>,
+}
+
+impl Sources {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Read the sources from a configuration file. A missing file has no
+    /// sources.
+    pub fn load(path: impl AsRef<Path>) -> Result<Self> {
+        match std::fs::read(path) {
+            Ok(data) => Ok(serde_json::from_slice(&data)?),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    /// Write the configuration file, replacing it atomically.
+    pub fn save(&self, path: impl AsRef<Path>) -> Result<()> {
+        let path = path.as_ref();
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        std::fs::write(&tmp, serde_json::to_vec_pretty(self)?)?;
+        std::fs::rename(&tmp, path)?;
+        Ok(())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &SourceConfig> {
+        self.sources.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.sources.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sources.is_empty()
+    }
+
+    /// Append `source`, replacing the settings of a source with the same
+    /// URL but keeping its position.
+    pub fn add(&mut self, source: SourceConfig) {
+        match self.sources.iter_mut().find(|s| s.url == source.url) {
+            Some(existing) => *existing = source,
+            None => self.sources.push(source),
+        }
+    }
+
+    /// Returns whether a source with `url` was configured.
+    pub fn remove(&mut self, url: &Url) -> bool {
+        let before = self.sources.len();
+        self.sources.retain(|s| &s.url != url);
+        self.sources.len() != before
+    }
+
+    /// A client for an IMGAPI source, honouring its insecure flag.
+    pub fn client(source: &SourceConfig) -> Result<Client> {
+        ClientBuilder::new(source.url.as_str())
+            .danger_accept_invalid_certs(source.insecure)
+            .build()
+    }
+
+    /// The [`Source`] for a configured source of any type.
+    pub fn open(source: &SourceConfig) -> Result<Box<dyn Source>> {
+        Ok(match source.source_type {
+            SourceType::Imgapi => Box::new(Self::client(source)?),
+            SourceType::Dsapi => Box::new(DsapiSource::new(source.url.clone(), source.insecure)?),
+            SourceType::Docker => Box::new(DockerSource::new(source.url.clone(), source.insecure)?),
+        })
+    }
+
+    /// The images of all sources that can list them, each UUID only once,
+    /// taken from the first source that has it.
+    pub async fn list_images(&self) -> Result<Vec<SourcedImage>> {
+        let mut seen = HashSet::new();
+        let mut images = vec![];
+        for source in &self.sources {
+            let listed = match Self::open(source)?.list().await {
+                Err(ImgapiError::Unsupported(_)) => {
+                    log::debug!("not listing {} source {}", source.source_type, source.url);
+                    continue;
+                }
+                listed => listed?,
+            };
+            for manifest in listed {
+                if seen.insert(manifest.uuid) {
+                    images.push(SourcedImage {
+                        manifest,
+                        source: source.url.clone(),
+                    });
+                }
+            }
+        }
+        Ok(images)
+    }
+}
+
+fn http_client(insecure: bool) -> Result<reqwest::Client> {
+    Ok(reqwest::Client::builder()
+        .danger_accept_invalid_certs(insecure)
+        .build()?)
+}
+
+//Error statuses of non IMGAPI servers, reported like those of the client.
+async fn checked(resp: reqwest::Response) -> Result<reqwest::Response> {
+    if resp.status().is_success() {
+        return Ok(resp);
+    }
+    let status = resp.status().as_u16();
+    let body = resp.text().await.unwrap_or_default();
+    Err(api_error(status, None, &body))
+}
+
+//Copy a response body into `writer`, digesting it on the way.
+async fn copy_digested(
+    resp: reqwest::Response,
+    writer: &mut (dyn AsyncWrite + Unpin + Send),
+) -> Result<Digests> {
+    let mut digest = MultiDigest::new();
+    let mut body = resp.bytes_stream();
+    while let Some(chunk) = body.next().await {
+        let chunk = chunk?;
+        digest.update(&chunk);
+        writer.write_all(&chunk).await?;
+    }
+    writer.flush().await?;
+    Ok(digest.finalize())
+}
@@ -0,0 +1,177 @@
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use reqwest::header::WWW_AUTHENTICATE;
+use reqwest::{Response, StatusCode};
+use serde::Deserialize;
+use tokio::io::AsyncWrite;
+use url::Url;
+use uuid::Uuid;
+
+use super::{checked, copy_digested, http_client, Source};
+use crate::client::expected_sha1;
+use crate::error::{ImgapiError, Result};
+use crate::manifest::Manifest;
+
+//Docker Hub is configured as docker.io but served from another host.
+const HUB_HOSTS: &[&str] = &["docker.io", "index.docker.io", "registry-1.docker.io"];
+const HUB_REGISTRY: &str = "https://registry-1.docker.io";
+
+/// A Docker registry speaking the v2 API, such as https://docker.io.
+///
+/// Registries have no listing of images by UUID, so only fetch_file is
+/// supported. It downloads the layer blob named by the `digest` of the file
+/// from the repository in the `docker:repo` tag, as IMGAPI records them for
+/// imported Docker images.
+#[derive(Debug, Clone)]
+pub struct DockerSource {
+    url: Url,
+    registry: Url,
+    http: reqwest::Client,
+}
+
+impl DockerSource {
+    pub fn new(url: Url, insecure: bool) -> Result<Self> {
+        let registry = if is_hub(&url) {
+            HUB_REGISTRY.parse()?
+        } else {
+            url.clone()
+        };
+        Ok(Self {
+            url,
+            registry,
+            http: http_client(insecure)?,
+        })
+    }
+
+    //Official images on Docker Hub live in the library namespace.
+    fn repo_path(&self, repo: &str) -> String {
+        if is_hub(&self.url) && !repo.contains('/') {
+            format!("library/{}", repo)
+        } else {
+            repo.to_string()
+        }
+    }
+
+    async fn blob(&self, repo: &str, digest: &str) -> Result<Response> {
+        let url = self
+            .registry
+            .join(&format!("/v2/{}/blobs/{}", repo, digest))?;
+        let resp = self.http.get(url.clone()).send().await?;
+        if resp.status() != StatusCode::UNAUTHORIZED {
+            return checked(resp).await;
+        }
+        //Anonymous pulls still need a token from the realm the registry names
+        let challenge = resp
+            .headers()
+            .get(WWW_AUTHENTICATE)
+            .and_then(|v| v.to_str().ok())
+            .and_then(|v| v.strip_prefix("Bearer "))
+            .map(challenge_params);
+        let Some(params) = challenge else {
+            return checked(resp).await;
+        };
+        let token = self.token(&params, repo).await?;
+        checked(self.http.get(url).bearer_auth(token).send().await?).await
+    }
+
+    async fn token(&self, params: &[(String, String)], repo: &str) -> Result<String> {
+        let param = |name: &str| {
+            params
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.as_str())
+        };
+        let realm = param("realm")
+            .ok_or_else(|| ImgapiError::InvalidHeader(WWW_AUTHENTICATE.as_str().to_string()))?;
+        let scope = format!("repository:{}:pull", repo);
+        let mut query = vec![("scope", param("scope").unwrap_or(&scope))];
+        query.extend(param("service").map(|service| ("service", service)));
+        let resp = checked(self.http.get(realm).query(&query).send().await?).await?;
+        let token: TokenResponse = resp.json().await?;
+        token
+            .token
+            .or(token.access_token)
+            .ok_or_else(|| ImgapiError::InvalidHeader("token".to_string()))
+    }
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    #[serde(default)]
+    token: Option<String>,
+    #[serde(default)]
+    access_token: Option<String>,
+}
+
+fn is_hub(url: &Url) -> bool {
+    url.host_str().is_some_and(|host| HUB_HOSTS.contains(&host))
+}
+
+//The key="value" pairs of a Bearer challenge. Values may contain commas,
+//e.g. scope="repository:foo:pull,push".
+fn challenge_params(challenge: &str) -> Vec<(String, String)> {
+    let mut params = vec![];
+    let mut rest = challenge.trim();
+    while let Some((key, after)) = rest.split_once('=') {
+        let key = key.trim().trim_start_matches(',').trim().to_string();
+        let (value, after) = match after.strip_prefix('"') {
+            Some(quoted) => match quoted.split_once('"') {
+                Some((value, after)) => (value, after),
+                None => (quoted, ""),
+            },
+            None => after.split_once(',').unwrap_or((after, "")),
+        };
+        params.push((key, value.to_string()));
+        rest = after;
+    }
+    params
+}
+
+impl Source for DockerSource {
+    fn url(&self) -> &Url {
+        &self.url
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<Manifest>>> {
+        async { Err(ImgapiError::Unsupported("listing images")) }.boxed()
+    }
+
+    fn get(&self, _uuid: Uuid) -> BoxFuture<'_, Result<Manifest>> {
+        async { Err(ImgapiError::Unsupported("getting images by UUID")) }.boxed()
+    }
+
+    fn fetch_file<'a>(
+        &'a self,
+        manifest: &'a Manifest,
+        index: usize,
+        writer: &'a mut (dyn AsyncWrite + Unpin + Send),
+    ) -> BoxFuture<'a, Result<u64>> {
+        async move {
+            let missing = || ImgapiError::MissingFile {
+                uuid: manifest.uuid,
+                index,
+            };
+            let repo = manifest.docker_repo().ok_or_else(missing)?;
+            let digest = manifest
+                .files
+                .get(index)
+                .and_then(|file| file.digest.as_deref())
+                .ok_or_else(missing)?;
+            let resp = self.blob(&self.repo_path(repo), digest).await?;
+            let digests = copy_digested(resp, writer).await?;
+
+            let mut checks = vec![];
+            if let Some(sha256) = digest.strip_prefix("sha256:") {
+                checks.push((sha256.to_ascii_lowercase(), digests.sha256_hex()));
+            }
+            checks.push((expected_sha1(manifest, index)?, digests.sha1_hex()));
+            for (expected, actual) in checks {
+                if expected != actual {
+                    return Err(ImgapiError::ChecksumMismatch { expected, actual });
+                }
+            }
+            Ok(digests.len)
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,87 @@
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use serde_json::Value;
+use tokio::io::AsyncWrite;
+use url::Url;
+use uuid::Uuid;
+
+use super::{checked, copy_digested, http_client, Source};
+use crate::client::{expected_sha1, normalize_base_url};
+use crate::error::{ImgapiError, Result};
+use crate::manifest::{migrate, Manifest, CURRENT_VERSION};
+
+#[doc = "A legacy DSAPI server, such as https://datasets.joyent.com/datasets"]
+#[derive(Debug, Clone)]
+pub struct DsapiSource {
+    url: Url,
+    http: reqwest::Client,
+}
+
+impl DsapiSource {
+    pub fn new(url: Url, insecure: bool) -> Result<Self> {
+        Ok(Self {
+            url,
+            http: http_client(insecure)?,
+        })
+    }
+
+    //Datasets as the server sends them, v=1 manifests with file URLs.
+    async fn datasets(&self) -> Result<Vec<Value>> {
+        let resp = checked(self.http.get(self.url.clone()).send().await?).await?;
+        Ok(resp.json().await?)
+    }
+
+    async fn dataset(&self, uuid: Uuid) -> Result<Value> {
+        let url = normalize_base_url(self.url.clone()).join(&uuid.to_string())?;
+        let resp = checked(self.http.get(url).send().await?).await?;
+        Ok(resp.json().await?)
+    }
+}
+
+//Datasets predate the v=2 manifest, which no longer carries file URLs.
+fn upgrade(dataset: Value) -> Result<Manifest> {
+    let migrated = migrate(dataset, CURRENT_VERSION)?;
+    Ok(serde_json::from_value(migrated.manifest)?)
+}
+
+impl Source for DsapiSource {
+    fn url(&self) -> &Url {
+        &self.url
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<Manifest>>> {
+        async move { self.datasets().await?.into_iter().map(upgrade).collect() }.boxed()
+    }
+
+    fn get(&self, uuid: Uuid) -> BoxFuture<'_, Result<Manifest>> {
+        async move { upgrade(self.dataset(uuid).await?) }.boxed()
+    }
+
+    fn fetch_file<'a>(
+        &'a self,
+        manifest: &'a Manifest,
+        index: usize,
+        writer: &'a mut (dyn AsyncWrite + Unpin + Send),
+    ) -> BoxFuture<'a, Result<u64>> {
+        async move {
+            let expected = expected_sha1(manifest, index)?;
+            let dataset = self.dataset(manifest.uuid).await?;
+            let url = dataset["files"][index]["url"]
+                .as_str()
+                .ok_or(ImgapiError::MissingFile {
+                    uuid: manifest.uuid,
+                    index,
+                })?;
+            let resp = checked(self.http.get(url).send().await?).await?;
+            let digests = copy_digested(resp, writer).await?;
+            if digests.sha1_hex() != expected {
+                return Err(ImgapiError::ChecksumMismatch {
+                    expected,
+                    actual: digests.sha1_hex(),
+                });
+            }
+            Ok(digests.len)
+        }
+        .boxed()
+    }
+}
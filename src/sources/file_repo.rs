@@ -0,0 +1,216 @@
+use futures::future::BoxFuture;
+use futures::FutureExt;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncRead, AsyncWrite, AsyncWriteExt};
+use url::Url;
+use uuid::Uuid;
+
+use super::Source;
+use crate::client::{expected_sha1, DigestReader};
+use crate::error::{ImgapiError, Result};
+use crate::manifest::Manifest;
+
+/// An image repository in a plain directory, e.g. on a USB disk or an NFS
+/// share, holding `<uuid>.manifest.json` and `<uuid>.<index>.file` for every
+/// image.
+///
+/// Files are written before the manifest, so readers only list images whose
+/// files are complete.
+#[derive(Debug, Clone)]
+pub struct FileRepo {
+    dir: PathBuf,
+    url: Url,
+}
+
+impl FileRepo {
+    pub fn new(dir: impl AsRef<Path>) -> Result<Self> {
+        let dir = std::path::absolute(dir)?;
+        let url = Url::from_directory_path(&dir).map_err(|()| {
+            ImgapiError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidInput,
+                format!("{} cannot be used as a repository", dir.display()),
+            ))
+        })?;
+        Ok(Self { dir, url })
+    }
+
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    pub fn manifest_path(&self, uuid: Uuid) -> PathBuf {
+        self.dir.join(format!("{}.manifest.json", uuid))
+    }
+
+    pub fn file_path(&self, uuid: Uuid, index: usize) -> PathBuf {
+        self.dir.join(format!("{}.{}.file", uuid, index))
+    }
+
+    /// Publish file `index` of `manifest` from `reader`. The content is
+    /// checked against the SHA-1 of the manifest, if it has one, before it
+    /// replaces an existing file.
+    pub async fn add_file<R: AsyncRead + Unpin>(
+        &self,
+        manifest: &Manifest,
+        index: usize,
+        reader: R,
+    ) -> Result<u64> {
+        let path = self.file_path(manifest.uuid, index);
+        let part = path.with_extension("part");
+        tokio::fs::create_dir_all(&self.dir).await?;
+        let copied = async {
+            let mut reader = DigestReader::new(reader);
+            let mut file = tokio::fs::File::create(&part).await?;
+            tokio::io::copy(&mut reader, &mut file).await?;
+            file.sync_all().await?;
+            let digests = reader.finalize();
+            if let Ok(expected) = expected_sha1(manifest, index) {
+                if digests.sha1_hex() != expected {
+                    return Err(ImgapiError::ChecksumMismatch {
+                        expected,
+                        actual: digests.sha1_hex(),
+                    });
+                }
+            }
+            Ok(digests.len)
+        }
+        .await;
+        match copied {
+            Ok(len) => {
+                tokio::fs::rename(&part, &path).await?;
+                Ok(len)
+            }
+            Err(e) => {
+                let _ = tokio::fs::remove_file(&part).await;
+                Err(e)
+            }
+        }
+    }
+
+    /// Publish `manifest`, making the image visible to readers. Add its
+    /// files first.
+    pub async fn add_manifest(&self, manifest: &Manifest) -> Result<()> {
+        let path = self.manifest_path(manifest.uuid);
+        let tmp = path.with_extension("tmp");
+        tokio::fs::create_dir_all(&self.dir).await?;
+        tokio::fs::write(&tmp, serde_json::to_vec_pretty(manifest)?).await?;
+        tokio::fs::rename(&tmp, &path).await?;
+        Ok(())
+    }
+
+    /// Copy image `uuid` with all its files from `source` into the
+    /// repository.
+    pub async fn import(&self, source: &dyn Source, uuid: Uuid) -> Result<Manifest> {
+        let manifest = source.get(uuid).await?;
+        tokio::fs::create_dir_all(&self.dir).await?;
+        for index in 0..manifest.files.len() {
+            //The source verifies what it sends
+            let path = self.file_path(uuid, index);
+            let part = path.with_extension("part");
+            let mut file = tokio::fs::File::create(&part).await?;
+            let fetched = source.fetch_file(&manifest, index, &mut file).await;
+            if let Err(e) = fetched {
+                let _ = tokio::fs::remove_file(&part).await;
+                return Err(e);
+            }
+            file.sync_all().await?;
+            tokio::fs::rename(&part, &path).await?;
+        }
+        self.add_manifest(&manifest).await?;
+        Ok(manifest)
+    }
+
+    /// Remove an image and its files. Returns whether it was published.
+    pub async fn remove(&self, uuid: Uuid) -> Result<bool> {
+        let Some(manifest) = self.read_manifest(uuid).await? else {
+            return Ok(false);
+        };
+        //The manifest goes first, so the image is never listed incomplete
+        tokio::fs::remove_file(self.manifest_path(uuid)).await?;
+        for index in 0..manifest.files.len() {
+            match tokio::fs::remove_file(self.file_path(uuid, index)).await {
+                Err(e) if e.kind() != std::io::ErrorKind::NotFound => return Err(e.into()),
+                _ => {}
+            }
+        }
+        Ok(true)
+    }
+
+    async fn read_manifest(&self, uuid: Uuid) -> Result<Option<Manifest>> {
+        match tokio::fs::read(self.manifest_path(uuid)).await {
+            Ok(data) => Ok(Some(serde_json::from_slice(&data)?)),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(None),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    async fn manifests(&self) -> Result<Vec<Manifest>> {
+        let mut entries = match tokio::fs::read_dir(&self.dir).await {
+            Ok(entries) => entries,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(vec![]),
+            Err(e) => return Err(e.into()),
+        };
+        let mut manifests = vec![];
+        while let Some(entry) = entries.next_entry().await? {
+            let name = entry.file_name();
+            let uuid = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".manifest.json"))
+                .and_then(|uuid| uuid.parse::<Uuid>().ok());
+            if let Some(manifest) = match uuid {
+                Some(uuid) => self.read_manifest(uuid).await?,
+                None => None,
+            } {
+                manifests.push(manifest);
+            }
+        }
+        manifests.sort_by_key(|m| m.published_at);
+        Ok(manifests)
+    }
+}
+
+impl Source for FileRepo {
+    fn url(&self) -> &Url {
+        &self.url
+    }
+
+    fn list(&self) -> BoxFuture<'_, Result<Vec<Manifest>>> {
+        self.manifests().boxed()
+    }
+
+    fn get(&self, uuid: Uuid) -> BoxFuture<'_, Result<Manifest>> {
+        async move {
+            self.read_manifest(uuid).await?.ok_or_else(|| {
+                ImgapiError::Io(std::io::Error::new(
+                    std::io::ErrorKind::NotFound,
+                    format!("image {} is not in {}", uuid, self.dir.display()),
+                ))
+            })
+        }
+        .boxed()
+    }
+
+    fn fetch_file<'a>(
+        &'a self,
+        manifest: &'a Manifest,
+        index: usize,
+        writer: &'a mut (dyn AsyncWrite + Unpin + Send),
+    ) -> BoxFuture<'a, Result<u64>> {
+        async move {
+            let expected = expected_sha1(manifest, index)?;
+            let file = tokio::fs::File::open(self.file_path(manifest.uuid, index)).await?;
+            let mut reader = DigestReader::new(file);
+            tokio::io::copy(&mut reader, writer).await?;
+            writer.flush().await?;
+            let digests = reader.finalize();
+            if digests.sha1_hex() != expected {
+                return Err(ImgapiError::ChecksumMismatch {
+                    expected,
+                    actual: digests.sha1_hex(),
+                });
+            }
+            Ok(digests.len)
+        }
+        .boxed()
+    }
+}
@@ -0,0 +1,390 @@
+//! A local cache directory of image manifests, keyed by UUID, and image
+//! files, keyed by SHA-1, so identical content is only fetched once.
+//!
+//! Layout below the root:
+//! `manifests/<uuid>.json`, `files/<sha1[..2]>/<sha1>` and `tmp/` for
+//! writes in progress, which are renamed into place once complete.
+//!
+//! Several processes may use a store at once. Because of the renames,
+//! reads need no locks. The put methods do not lock, since an image takes
+//! several of them: code importing an image should hold
+//! [`Store::lock_image`] while it puts the manifest and files, which keeps
+//! [`Store::gc`], taking the store wide lock, from removing files whose
+//! manifest is not written yet. `Downloader::with_store` does so. The locks
+//! are advisory, in `lock` and `locks/<uuid>.lock`.
+//!
+//! With the sqlite feature, [`Store::index`] keeps an `index.sqlite` of the
+//! manifests for lookups without parsing every manifest. Once created, it
+//! is updated whenever manifests are put or removed.
+
+use sha1::{Digest, Sha1};
+use std::collections::HashSet;
+use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::error::{ImgapiError, Result};
+use crate::id::ImageId;
+use crate::manifest::Manifest;
+
+#[cfg(feature = "sqlite")]
+mod index;
+
+#[cfg(feature = "sqlite")]
+pub use index::{Index, IndexEntry, IndexQuery, IndexQueryBuilder, IndexQueryBuilderError};
+
+#[doc = "An advisory lock on a store or one of its images, released on drop"]
+#[derive(Debug)]
+#[must_use = "the lock is released when dropped"]
+pub struct StoreLock {
+    _store: File,
+    _image: Option<File>,
+}
+
+#[doc = "What Store::gc removed"]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GcReport {
+    pub manifests: Vec<Uuid>,
+    //SHA-1s of the removed files.
+    pub files: Vec<String>,
+    //Size of the removed manifests and files.
+    pub reclaimed_bytes: u64,
+}
+
+#[doc = "A local image cache directory"]
+#[derive(Debug, Clone)]
+pub struct Store {
+    root: PathBuf,
+}
+
+impl Store {
+    /// Open the store at `root`, creating its directories if needed.
+    pub fn open(root: impl Into<PathBuf>) -> Result<Self> {
+        let store = Self { root: root.into() };
+        for dir in ["manifests", "files", "tmp", "locks"] {
+            fs::create_dir_all(store.root.join(dir))?;
+        }
+        Ok(store)
+    }
+
+    pub fn root(&self) -> &Path {
+        &self.root
+    }
+
+    pub fn manifest_path(&self, uuid: Uuid) -> PathBuf {
+        self.root.join("manifests").join(format!("{}.json", uuid))
+    }
+
+    /// Where the file with `sha1` is stored, whether or not it is present.
+    pub fn file_path(&self, sha1: &str) -> Result<PathBuf> {
+        let sha1 = check_sha1(sha1)?;
+        Ok(self.root.join("files").join(&sha1[..2]).join(sha1))
+    }
+
+    pub fn put_manifest(&self, manifest: &Manifest) -> Result<()> {
+        let data = serde_json::to_vec_pretty(manifest)?;
+        self.write_atomic(&self.manifest_path(manifest.uuid), |file| {
+            file.write_all(&data)?;
+            Ok(())
+        })?;
+        #[cfg(feature = "sqlite")]
+        if self.index_path().exists() {
+            self.index()?.insert(manifest)?;
+        }
+        Ok(())
+    }
+
+    pub fn manifest(&self, uuid: Uuid) -> Result<Option<Manifest>> {
+        let data = match fs::read(self.manifest_path(uuid)) {
+            Ok(data) => data,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+        let manifest: Manifest = serde_json::from_slice(&data)?;
+        //A manifest stored under the wrong name is as bad as a corrupt one
+        if manifest.uuid != uuid {
+            return Err(ImgapiError::Io(std::io::Error::new(
+                std::io::ErrorKind::InvalidData,
+                format!("{} holds the manifest of {}", uuid, manifest.uuid),
+            )));
+        }
+        Ok(Some(manifest))
+    }
+
+    /// All stored manifests, in no particular order.
+    pub fn manifests(&self) -> Result<Vec<Manifest>> {
+        let mut manifests = vec![];
+        for uuid in self.manifest_uuids()? {
+            if let Some(manifest) = self.manifest(uuid)? {
+                manifests.push(manifest);
+            }
+        }
+        Ok(manifests)
+    }
+
+    /// The stored image `id` refers to, see [`ImageId::select`].
+    pub fn resolve(&self, id: &ImageId) -> Result<Manifest> {
+        if let ImageId::Uuid(uuid) = id {
+            return self
+                .manifest(*uuid)?
+                .ok_or_else(|| ImgapiError::ImageNotFound(id.clone()));
+        }
+        id.select(self.manifests()?)
+    }
+
+    pub fn manifest_uuids(&self) -> Result<Vec<Uuid>> {
+        let mut uuids = vec![];
+        for entry in fs::read_dir(self.root.join("manifests"))? {
+            let name = entry?.file_name();
+            let uuid = name
+                .to_str()
+                .and_then(|name| name.strip_suffix(".json"))
+                .and_then(|uuid| uuid.parse::<Uuid>().ok());
+            uuids.extend(uuid);
+        }
+        Ok(uuids)
+    }
+
+    /// Returns whether the manifest was present.
+    pub fn remove_manifest(&self, uuid: Uuid) -> Result<bool> {
+        #[cfg(feature = "sqlite")]
+        if self.index_path().exists() {
+            self.index()?.remove(uuid)?;
+        }
+        remove(&self.manifest_path(uuid))
+    }
+
+    #[cfg(feature = "sqlite")]
+    pub fn index_path(&self) -> PathBuf {
+        self.root.join("index.sqlite")
+    }
+
+    /// Open the index of the store, building it from the stored manifests
+    /// when it does not exist yet.
+    #[cfg(feature = "sqlite")]
+    pub fn index(&self) -> Result<Index> {
+        let path = self.index_path();
+        if path.exists() {
+            return Index::open(path);
+        }
+        //Built aside, so no other process opens a half built index
+        let tmp = self.root.join("tmp").join(Uuid::new_v4().to_string());
+        let mut index = Index::open(&tmp)?;
+        index.rebuild(self)?;
+        drop(index);
+        fs::rename(&tmp, &path)?;
+        Index::open(path)
+    }
+
+    pub fn has_file(&self, sha1: &str) -> bool {
+        self.file_path(sha1).is_ok_and(|path| path.is_file())
+    }
+
+    /// Store the content of `reader` as the file with `sha1`. The content is
+    /// hashed while it is written and only stored if it matches.
+    pub fn put_file<R: Read>(&self, sha1: &str, mut reader: R) -> Result<PathBuf> {
+        let path = self.file_path(sha1)?;
+        let expected = sha1.to_ascii_lowercase();
+        self.write_atomic(&path, |file| {
+            let mut hasher = Sha1::new();
+            let mut buf = vec![0u8; 64 * 1024];
+            loop {
+                let n = reader.read(&mut buf)?;
+                if n == 0 {
+                    break;
+                }
+                hasher.update(&buf[..n]);
+                file.write_all(&buf[..n])?;
+            }
+            check_digest(&expected, hasher)
+        })?;
+        Ok(path)
+    }
+
+    /// Move the complete file at `source`, e.g. a finished download, into
+    /// the store after checking its SHA-1.
+    pub fn import_file(&self, sha1: &str, source: &Path) -> Result<PathBuf> {
+        let path = self.file_path(sha1)?;
+        check_digest(&sha1.to_ascii_lowercase(), hash_file(source)?)?;
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if fs::rename(source, &path).is_err() {
+            //Across file systems
+            return self.put_file(sha1, File::open(source)?);
+        }
+        Ok(path)
+    }
+
+    /// Open the file with `sha1` after checking that its content still
+    /// matches. Returns None if it is not stored.
+    pub fn open_file(&self, sha1: &str) -> Result<Option<File>> {
+        if !self.has_file(sha1) {
+            return Ok(None);
+        }
+        self.verify_file(sha1)?;
+        Ok(Some(File::open(self.file_path(sha1)?)?))
+    }
+
+    /// Check the stored file with `sha1` against its name.
+    pub fn verify_file(&self, sha1: &str) -> Result<()> {
+        check_digest(
+            &sha1.to_ascii_lowercase(),
+            hash_file(&self.file_path(sha1)?)?,
+        )
+    }
+
+    /// Returns whether the file was present.
+    pub fn remove_file(&self, sha1: &str) -> Result<bool> {
+        remove(&self.file_path(sha1)?)
+    }
+
+    /// Take the store wide lock, waiting for all image locks to be
+    /// released, e.g. for maintenance.
+    pub fn lock(&self) -> Result<StoreLock> {
+        let store = self.lock_file(&self.root.join("lock"))?;
+        store.lock()?;
+        Ok(StoreLock {
+            _store: store,
+            _image: None,
+        })
+    }
+
+    /// Lock image `uuid` for importing or removing it. Locks of different
+    /// images do not wait for each other.
+    pub fn lock_image(&self, uuid: Uuid) -> Result<StoreLock> {
+        let store = self.lock_file(&self.root.join("lock"))?;
+        store.lock_shared()?;
+        let image = self.lock_file(&self.root.join("locks").join(format!("{}.lock", uuid)))?;
+        image.lock()?;
+        Ok(StoreLock {
+            _store: store,
+            _image: Some(image),
+        })
+    }
+
+    fn lock_file(&self, path: &Path) -> Result<File> {
+        Ok(fs::OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .write(true)
+            .open(path)?)
+    }
+
+    /// Remove everything not needed by the `installed` images: manifests
+    /// outside of their origin chains and files none of the remaining
+    /// manifests refer to. Writes in progress in tmp/ are left alone.
+    ///
+    /// Takes the store wide lock, so it must not be called while holding a
+    /// lock of the same store.
+    pub fn gc(&self, installed: impl IntoIterator<Item = Uuid>) -> Result<GcReport> {
+        let _lock = self.lock()?;
+        let mut live = HashSet::new();
+        let mut referenced = HashSet::new();
+        for uuid in installed {
+            let mut next = Some(uuid);
+            //live.insert also stops at cycles and chains already walked
+            while let Some(uuid) = next.filter(|uuid| live.insert(*uuid)) {
+                let Some(manifest) = self.manifest(uuid)? else {
+                    log::debug!("origin {} is not in the store", uuid);
+                    break;
+                };
+                referenced.extend(
+                    manifest
+                        .files
+                        .iter()
+                        .map(|file| file.sha1.to_ascii_lowercase()),
+                );
+                next = manifest.origin;
+            }
+        }
+
+        let mut report = GcReport::default();
+        for uuid in self.manifest_uuids()? {
+            if !live.contains(&uuid) {
+                let size = fs::metadata(self.manifest_path(uuid))?.len();
+                if self.remove_manifest(uuid)? {
+                    report.manifests.push(uuid);
+                    report.reclaimed_bytes += size;
+                }
+            }
+        }
+        for shard in fs::read_dir(self.root.join("files"))? {
+            let shard = shard?.path();
+            if !shard.is_dir() {
+                continue;
+            }
+            for entry in fs::read_dir(&shard)? {
+                let entry = entry?;
+                let name = entry.file_name().to_string_lossy().into_owned();
+                if referenced.contains(&name) {
+                    continue;
+                }
+                let size = entry.metadata()?.len();
+                if remove(&entry.path())? {
+                    report.files.push(name);
+                    report.reclaimed_bytes += size;
+                }
+            }
+            //Only succeeds once the shard is empty
+            let _ = fs::remove_dir(&shard);
+        }
+        Ok(report)
+    }
+
+    //Write `path` through a temporary file that is only renamed into place
+    //once `write` succeeded.
+    fn write_atomic(&self, path: &Path, write: impl FnOnce(&mut File) -> Result<()>) -> Result<()> {
+        let tmp = self.root.join("tmp").join(Uuid::new_v4().to_string());
+        let written = File::create(&tmp)
+            .map_err(ImgapiError::from)
+            .and_then(|mut file| {
+                write(&mut file)?;
+                file.sync_all()?;
+                Ok(())
+            });
+        let renamed = written.and_then(|()| {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+            Ok(fs::rename(&tmp, path)?)
+        });
+        if renamed.is_err() {
+            let _ = fs::remove_file(&tmp);
+        }
+        renamed
+    }
+}
+
+fn check_sha1(sha1: &str) -> Result<String> {
+    if sha1.len() != 40 || !sha1.bytes().all(|b| b.is_ascii_hexdigit()) {
+        return Err(ImgapiError::InvalidSha1(sha1.to_string()));
+    }
+    Ok(sha1.to_ascii_lowercase())
+}
+
+fn check_digest(expected: &str, hasher: Sha1) -> Result<()> {
+    let actual = hex::encode(hasher.finalize());
+    if actual != expected {
+        return Err(ImgapiError::ChecksumMismatch {
+            expected: expected.to_string(),
+            actual,
+        });
+    }
+    Ok(())
+}
+
+fn hash_file(path: &Path) -> Result<Sha1> {
+    let mut hasher = Sha1::new();
+    std::io::copy(&mut File::open(path)?, &mut hasher)?;
+    Ok(hasher)
+}
+
+fn remove(path: &Path) -> Result<bool> {
+    match fs::remove_file(path) {
+        Ok(()) => Ok(true),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(e) => Err(e.into()),
+    }
+}
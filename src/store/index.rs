@@ -0,0 +1,262 @@
+use derive_builder::Builder;
+use indexmap::IndexMap;
+use rusqlite::{params, params_from_iter, Connection, OptionalExtension, Transaction};
+use std::path::Path;
+use std::time::Duration;
+use uuid::Uuid;
+
+use super::Store;
+use crate::error::Result;
+use crate::manifest::{ImageOs, ImageType, Manifest};
+use crate::ser::{from_wire_name, wire_name};
+use crate::timestamp::Timestamp;
+
+const SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS images (
+        uuid TEXT PRIMARY KEY,
+        name TEXT NOT NULL,
+        version TEXT NOT NULL,
+        os TEXT NOT NULL,
+        type TEXT NOT NULL,
+        published_at INTEGER
+    );
+    CREATE INDEX IF NOT EXISTS images_name ON images (name, version);
+    CREATE TABLE IF NOT EXISTS tags (
+        uuid TEXT NOT NULL,
+        key TEXT NOT NULL,
+        value TEXT NOT NULL,
+        PRIMARY KEY (uuid, key)
+    );
+    CREATE INDEX IF NOT EXISTS tags_key ON tags (key, value);
+";
+
+#[doc = "A SQLite index of the manifests of a store, for fast lookups"]
+#[derive(Debug)]
+pub struct Index {
+    conn: Connection,
+}
+
+#[doc = "The indexed fields of a manifest"]
+#[derive(Debug, Clone, PartialEq)]
+pub struct IndexEntry {
+    pub uuid: Uuid,
+    pub name: String,
+    pub version: String,
+    pub os: ImageOs,
+    pub image_type: ImageType,
+    //Second precision.
+    pub published_at: Option<Timestamp>,
+    //Values other than strings are indexed as JSON, e.g. true.
+    pub tags: IndexMap<String, String>,
+}
+
+#[doc = "Filters for Index::query, all of them must match"]
+#[derive(Debug, Clone, Default, Builder)]
+pub struct IndexQuery {
+    #[builder(setter(into, strip_option), default)]
+    pub name: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub version: Option<String>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub os: Option<ImageOs>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub image_type: Option<ImageType>,
+
+    //Use the tag() setter to add entries.
+    #[builder(setter(custom), default)]
+    pub tags: IndexMap<String, String>,
+
+    //Only images published at or after this time.
+    #[builder(setter(into, strip_option), default)]
+    pub published_after: Option<Timestamp>,
+
+    #[builder(setter(into, strip_option), default)]
+    pub limit: Option<u32>,
+}
+
+impl IndexQueryBuilder {
+    pub fn tag(&mut self, key: impl Into<String>, value: impl Into<String>) -> &mut Self {
+        self.tags
+            .get_or_insert_with(IndexMap::new)
+            .insert(key.into(), value.into());
+        self
+    }
+}
+
+impl Index {
+    pub fn open(path: impl AsRef<Path>) -> Result<Self> {
+        Self::init(Connection::open(path)?)
+    }
+
+    pub fn open_in_memory() -> Result<Self> {
+        Self::init(Connection::open_in_memory()?)
+    }
+
+    fn init(conn: Connection) -> Result<Self> {
+        //Other processes importing into the same store
+        conn.busy_timeout(Duration::from_secs(10))?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Add `manifest`, replacing an earlier version of it.
+    pub fn insert(&mut self, manifest: &Manifest) -> Result<()> {
+        let tx = self.conn.transaction()?;
+        insert(&tx, manifest)?;
+        tx.commit()?;
+        Ok(())
+    }
+
+    /// Returns whether the image was indexed.
+    pub fn remove(&mut self, uuid: Uuid) -> Result<bool> {
+        let tx = self.conn.transaction()?;
+        let uuid = uuid.to_string();
+        tx.execute("DELETE FROM tags WHERE uuid = ?1", [&uuid])?;
+        let removed = tx.execute("DELETE FROM images WHERE uuid = ?1", [&uuid])?;
+        tx.commit()?;
+        Ok(removed > 0)
+    }
+
+    /// Replace the index content with the manifests of `store`. Returns the
+    /// number of indexed images.
+    pub fn rebuild(&mut self, store: &Store) -> Result<usize> {
+        let manifests = store.manifests()?;
+        //Readers see the old or the new content, never a partial one
+        let tx = self.conn.transaction()?;
+        tx.execute_batch("DELETE FROM tags; DELETE FROM images;")?;
+        for manifest in &manifests {
+            insert(&tx, manifest)?;
+        }
+        tx.commit()?;
+        Ok(manifests.len())
+    }
+
+    pub fn get(&self, uuid: Uuid) -> Result<Option<IndexEntry>> {
+        let row = self
+            .conn
+            .query_row(
+                "SELECT uuid, name, version, os, type, published_at FROM images WHERE uuid = ?1",
+                [uuid.to_string()],
+                Row::read,
+            )
+            .optional()?;
+        row.map(|row| self.entry(row)).transpose()
+    }
+
+    /// The matching images, oldest first.
+    pub fn query(&self, query: &IndexQuery) -> Result<Vec<IndexEntry>> {
+        let mut sql =
+            "SELECT uuid, name, version, os, type, published_at FROM images WHERE 1".to_string();
+        let mut values: Vec<rusqlite::types::Value> = vec![];
+        let mut filter = |column: &str, value: Option<rusqlite::types::Value>| {
+            if let Some(value) = value {
+                values.push(value);
+                sql.push_str(&format!(" AND {} ?{}", column, values.len()));
+            }
+        };
+        filter("name =", query.name.clone().map(Into::into));
+        filter("version =", query.version.clone().map(Into::into));
+        filter("os =", query.os.as_ref().map(|os| wire_name(os).into()));
+        filter(
+            "type =",
+            query.image_type.as_ref().map(|t| wire_name(t).into()),
+        );
+        filter(
+            "published_at >=",
+            query.published_after.as_ref().map(|ts| ts.to_unix().into()),
+        );
+        for (key, value) in &query.tags {
+            values.push(key.clone().into());
+            values.push(value.clone().into());
+            sql.push_str(&format!(
+                " AND uuid IN (SELECT uuid FROM tags WHERE key = ?{} AND value = ?{})",
+                values.len() - 1,
+                values.len()
+            ));
+        }
+        sql.push_str(" ORDER BY published_at, uuid");
+        if let Some(limit) = query.limit {
+            sql.push_str(&format!(" LIMIT {}", limit));
+        }
+
+        let mut stmt = self.conn.prepare(&sql)?;
+        let rows = stmt
+            .query_map(params_from_iter(values), Row::read)?
+            .collect::<rusqlite::Result<Vec<_>>>()?;
+        rows.into_iter().map(|row| self.entry(row)).collect()
+    }
+
+    fn entry(&self, row: Row) -> Result<IndexEntry> {
+        let mut stmt = self
+            .conn
+            .prepare_cached("SELECT key, value FROM tags WHERE uuid = ?1 ORDER BY key")?;
+        let tags = stmt
+            .query_map([&row.uuid], |r| Ok((r.get(0)?, r.get(1)?)))?
+            .collect::<rusqlite::Result<_>>()?;
+        Ok(IndexEntry {
+            uuid: row.uuid.parse().map_err(|_| {
+                std::io::Error::new(
+                    std::io::ErrorKind::InvalidData,
+                    format!("invalid UUID {:?} in index", row.uuid),
+                )
+            })?,
+            name: row.name,
+            version: row.version,
+            os: from_wire_name(row.os)?,
+            image_type: from_wire_name(row.image_type)?,
+            published_at: row.published_at.and_then(Timestamp::from_unix),
+            tags,
+        })
+    }
+}
+
+//A row of the images table.
+struct Row {
+    uuid: String,
+    name: String,
+    version: String,
+    os: String,
+    image_type: String,
+    published_at: Option<i64>,
+}
+
+impl Row {
+    fn read(row: &rusqlite::Row<'_>) -> rusqlite::Result<Self> {
+        Ok(Self {
+            uuid: row.get(0)?,
+            name: row.get(1)?,
+            version: row.get(2)?,
+            os: row.get(3)?,
+            image_type: row.get(4)?,
+            published_at: row.get(5)?,
+        })
+    }
+}
+
+//Add `manifest` as part of `tx`.
+fn insert(tx: &Transaction, manifest: &Manifest) -> Result<()> {
+    let uuid = manifest.uuid.to_string();
+    tx.execute("DELETE FROM tags WHERE uuid = ?1", [&uuid])?;
+    tx.execute(
+        "INSERT OR REPLACE INTO images (uuid, name, version, os, type, published_at)
+         VALUES (?1, ?2, ?3, ?4, ?5, ?6)",
+        params![
+            uuid,
+            manifest.name,
+            manifest.version,
+            wire_name(&manifest.os),
+            wire_name(&manifest.image_type),
+            manifest.published_at.as_ref().map(Timestamp::to_unix),
+        ],
+    )?;
+    for (key, value) in manifest.tags.iter().flatten() {
+        tx.execute(
+            "INSERT INTO tags (uuid, key, value) VALUES (?1, ?2, ?3)",
+            params![uuid, key, value.to_string()],
+        )?;
+    }
+    Ok(())
+}
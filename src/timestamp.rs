@@ -0,0 +1,206 @@
+//! Timestamp type used for manifest dates such as `published_at`.
+//!
+//! [`Timestamp`] is the same type whatever backend features are enabled, so
+//! a crate enabling `time` does not change it for others in the build.
+//! RFC 3339 dates are parsed with `chrono` (default) or `time`, and every
+//! enabled backend adds conversions to and from its own date type.
+
+#[cfg(not(any(feature = "chrono", feature = "time")))]
+compile_error!("either the `chrono` or the `time` feature must be enabled");
+
+use miette::Diagnostic;
+use serde::{de::Error as _, Deserialize, Deserializer, Serialize, Serializer};
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+
+const SECS_PER_DAY: i64 = 86_400;
+//0000-01-01T00:00:00Z and 9999-12-31T23:59:59Z, the range RFC 3339 can express.
+const MIN_UNIX: i64 = -62_167_219_200;
+const MAX_UNIX: i64 = 253_402_300_799;
+
+#[doc = "A UTC instant with nanosecond precision"]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Timestamp {
+    secs: i64,
+    //Always below one second.
+    nanos: u32,
+}
+
+#[derive(Debug, Clone, Error, Diagnostic, PartialEq, Eq)]
+#[error("invalid RFC 3339 timestamp {0:?}")]
+#[diagnostic(
+    code(imgapi::timestamp::invalid),
+    help("use a date such as 2023-03-01T12:00:00Z")
+)]
+pub struct ParseTimestampError(pub String);
+
+#[derive(Debug, Clone, Error, Diagnostic, PartialEq, Eq)]
+#[error("timestamp {0} is out of range")]
+#[diagnostic(code(imgapi::timestamp::range))]
+pub struct TimestampRangeError(pub Timestamp);
+
+impl Timestamp {
+    /// The current time.
+    pub fn now() -> Self {
+        SystemTime::now().into()
+    }
+
+    /// Timestamp for `secs` seconds since the unix epoch, if representable.
+    pub fn from_unix(secs: i64) -> Option<Self> {
+        (MIN_UNIX..=MAX_UNIX)
+            .contains(&secs)
+            .then_some(Timestamp { secs, nanos: 0 })
+    }
+
+    /// Seconds since the unix epoch.
+    pub fn to_unix(&self) -> i64 {
+        self.secs
+    }
+
+    /// Parse an RFC 3339 date as used by IMGAPI, e.g. `2023-03-01T12:00:00Z`.
+    pub fn parse_rfc3339(s: &str) -> Option<Self> {
+        #[cfg(feature = "chrono")]
+        return chrono::DateTime::parse_from_rfc3339(s)
+            .ok()
+            .map(|ts| ts.with_timezone(&chrono::Utc).into());
+        #[cfg(not(feature = "chrono"))]
+        return time::OffsetDateTime::parse(s, &time::format_description::well_known::Rfc3339)
+            .ok()
+            .map(Into::into);
+    }
+
+    /// Format as RFC 3339 in UTC, with as many fractional digits (0, 3, 6 or
+    /// 9) as needed. Independent of the backend.
+    pub fn to_rfc3339(&self) -> String {
+        let days = self.secs.div_euclid(SECS_PER_DAY);
+        let secs = self.secs.rem_euclid(SECS_PER_DAY);
+        let (year, month, day) = civil_from_days(days);
+        let mut out = format!(
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}",
+            year,
+            month,
+            day,
+            secs / 3600,
+            secs / 60 % 60,
+            secs % 60
+        );
+        match self.nanos {
+            0 => {}
+            n if n % 1_000_000 == 0 => out.push_str(&format!(".{:03}", n / 1_000_000)),
+            n if n % 1_000 == 0 => out.push_str(&format!(".{:06}", n / 1_000)),
+            n => out.push_str(&format!(".{:09}", n)),
+        }
+        out.push('Z');
+        out
+    }
+}
+
+//Year, month and day of the proleptic Gregorian calendar for a count of days
+//since 1970-01-01, after Howard Hinnant's civil_from_days.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+impl Display for Timestamp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.to_rfc3339())
+    }
+}
+
+impl FromStr for Timestamp {
+    type Err = ParseTimestampError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Timestamp::parse_rfc3339(s).ok_or_else(|| ParseTimestampError(s.into()))
+    }
+}
+
+impl From<SystemTime> for Timestamp {
+    fn from(time: SystemTime) -> Self {
+        match time.duration_since(UNIX_EPOCH) {
+            Ok(after) => Timestamp {
+                secs: after.as_secs() as i64,
+                nanos: after.subsec_nanos(),
+            },
+            Err(e) => {
+                let before = e.duration();
+                match before.subsec_nanos() {
+                    0 => Timestamp {
+                        secs: -(before.as_secs() as i64),
+                        nanos: 0,
+                    },
+                    n => Timestamp {
+                        secs: -(before.as_secs() as i64) - 1,
+                        nanos: 1_000_000_000 - n,
+                    },
+                }
+            }
+        }
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl From<chrono::DateTime<chrono::Utc>> for Timestamp {
+    fn from(ts: chrono::DateTime<chrono::Utc>) -> Self {
+        Timestamp {
+            secs: ts.timestamp(),
+            //chrono puts leap seconds into the nanoseconds
+            nanos: ts.timestamp_subsec_nanos().min(999_999_999),
+        }
+    }
+}
+
+#[cfg(feature = "chrono")]
+impl TryFrom<Timestamp> for chrono::DateTime<chrono::Utc> {
+    type Error = TimestampRangeError;
+
+    fn try_from(ts: Timestamp) -> Result<Self, Self::Error> {
+        chrono::DateTime::from_timestamp(ts.secs, ts.nanos).ok_or(TimestampRangeError(ts))
+    }
+}
+
+#[cfg(feature = "time")]
+impl From<time::OffsetDateTime> for Timestamp {
+    fn from(ts: time::OffsetDateTime) -> Self {
+        Timestamp {
+            secs: ts.unix_timestamp(),
+            nanos: ts.nanosecond(),
+        }
+    }
+}
+
+//In UTC.
+#[cfg(feature = "time")]
+impl TryFrom<Timestamp> for time::OffsetDateTime {
+    type Error = TimestampRangeError;
+
+    fn try_from(ts: Timestamp) -> Result<Self, Self::Error> {
+        let nanos = i128::from(ts.secs) * 1_000_000_000 + i128::from(ts.nanos);
+        time::OffsetDateTime::from_unix_timestamp_nanos(nanos).map_err(|_| TimestampRangeError(ts))
+    }
+}
+
+impl Serialize for Timestamp {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(&self.to_rfc3339())
+    }
+}
+
+impl<'de> Deserialize<'de> for Timestamp {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(D::Error::custom)
+    }
+}
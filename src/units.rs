@@ -0,0 +1,161 @@
+//! Size newtypes. IMGAPI uses MiB for `image_size`, `min_ram` and `max_ram`
+//! but bytes for `files[].size`; keeping them apart makes mixing them up a
+//! compile error.
+
+use miette::Diagnostic;
+use serde::{Deserialize, Serialize};
+use std::fmt::Display;
+use std::str::FromStr;
+use thiserror::Error;
+
+const KIB: u64 = 1024;
+const MIB: u64 = 1024 * KIB;
+const GIB: u64 = 1024 * MIB;
+const TIB: u64 = 1024 * GIB;
+
+#[derive(
+    Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(transparent)]
+pub struct MiB(pub u64);
+
+#[derive(
+    Deserialize, Serialize, Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash,
+)]
+#[serde(transparent)]
+pub struct Bytes(pub u64);
+
+#[derive(Debug, Clone, Error, Diagnostic, PartialEq, Eq)]
+pub enum ParseSizeError {
+    #[error("invalid size {0:?}")]
+    #[diagnostic(
+        code(imgapi::units::invalid),
+        help("use a whole number with an optional K, M, G or T suffix, e.g. 512M or 8G")
+    )]
+    Invalid(String),
+
+    #[error("size {0:?} is not a whole number of MiB")]
+    #[diagnostic(code(imgapi::units::not_whole_mib))]
+    NotWholeMiB(String),
+
+    #[error("size {0:?} is too large")]
+    #[diagnostic(code(imgapi::units::overflow))]
+    Overflow(String),
+}
+
+impl MiB {
+    pub fn from_gib(gib: u64) -> Self {
+        MiB(gib.saturating_mul(1024))
+    }
+
+    pub fn get(self) -> u64 {
+        self.0
+    }
+
+    /// The same size in bytes, saturating at `u64::MAX`.
+    pub fn to_bytes(self) -> Bytes {
+        Bytes(self.0.saturating_mul(MIB))
+    }
+}
+
+impl Bytes {
+    pub fn get(self) -> u64 {
+        self.0
+    }
+
+    /// The smallest number of MiB that holds this many bytes.
+    pub fn to_mib_ceil(self) -> MiB {
+        MiB(self.0.div_ceil(MIB))
+    }
+}
+
+impl From<MiB> for Bytes {
+    fn from(value: MiB) -> Self {
+        value.to_bytes()
+    }
+}
+
+impl TryFrom<Bytes> for MiB {
+    type Error = ParseSizeError;
+
+    fn try_from(value: Bytes) -> Result<Self, Self::Error> {
+        if !value.0.is_multiple_of(MIB) {
+            return Err(ParseSizeError::NotWholeMiB(value.to_string()));
+        }
+        Ok(MiB(value.0 / MIB))
+    }
+}
+
+/// Parse "8G", "512M", "1024k", "20GiB" or a plain number with `default_unit`.
+fn parse_bytes(s: &str, default_unit: u64) -> Result<u64, ParseSizeError> {
+    let trimmed = s.trim();
+    let split = trimmed
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(trimmed.len());
+    let (number, suffix) = trimmed.split_at(split);
+    let number: u64 = number
+        .parse()
+        .map_err(|_| ParseSizeError::Invalid(s.into()))?;
+
+    let suffix = suffix.trim().to_ascii_lowercase();
+    let base = suffix
+        .strip_suffix("ib")
+        .or_else(|| suffix.strip_suffix('b'))
+        .unwrap_or(&suffix);
+    let unit = match base {
+        "" if suffix.is_empty() => default_unit,
+        "" => 1,
+        "k" => KIB,
+        "m" => MIB,
+        "g" => GIB,
+        "t" => TIB,
+        _ => return Err(ParseSizeError::Invalid(s.into())),
+    };
+
+    number
+        .checked_mul(unit)
+        .ok_or_else(|| ParseSizeError::Overflow(s.into()))
+}
+
+impl FromStr for MiB {
+    type Err = ParseSizeError;
+
+    /// Plain numbers are taken as MiB.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let bytes = parse_bytes(s, MIB)?;
+        MiB::try_from(Bytes(bytes)).map_err(|_| ParseSizeError::NotWholeMiB(s.into()))
+    }
+}
+
+impl FromStr for Bytes {
+    type Err = ParseSizeError;
+
+    /// Plain numbers are taken as bytes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        parse_bytes(s, 1).map(Bytes)
+    }
+}
+
+fn fmt_bytes(bytes: u64, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    for (unit, suffix) in [(TIB, "T"), (GIB, "G"), (MIB, "M"), (KIB, "K")] {
+        if bytes >= unit && bytes.is_multiple_of(unit) {
+            return write!(f, "{}{}", bytes / unit, suffix);
+        }
+    }
+    write!(f, "{}", bytes)
+}
+
+impl Display for MiB {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.0.checked_mul(MIB) {
+            Some(bytes) => fmt_bytes(bytes, f),
+            None => write!(f, "{}M", self.0),
+        }
+    }
+}
+
+impl Display for Bytes {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        fmt_bytes(self.0, f)
+    }
+}